@@ -0,0 +1,520 @@
+use clap::Args;
+use colored::*;
+use serde_json::Value;
+use std::{fs, io::Read, path::Path, collections::HashMap};
+use crate::lockfile::{LockFile, LOCK_FILE};
+use crate::requirement::normalize_name;
+use crate::settings::Config;
+use crate::version::Version;
+use crate::utils::*;
+
+const OSV_URL: &str = "https://api.osv.dev";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Severity {
+    Low,
+    Moderate,
+    High,
+    Critical,
+}
+
+impl Severity {
+    fn parse(input: &str) -> Option<Severity> {
+        match input.to_lowercase().as_str() {
+            "low" => Some(Severity::Low),
+            "moderate" | "medium" => Some(Severity::Moderate),
+            "high" => Some(Severity::High),
+            "critical" => Some(Severity::Critical),
+            _ => None,
+        }
+    }
+
+    fn from_score(score: f64) -> Option<Severity> {
+        match score {
+            s if s >= 9.0 => Some(Severity::Critical),
+            s if s >= 7.0 => Some(Severity::High),
+            s if s >= 4.0 => Some(Severity::Moderate),
+            s if s > 0.0 => Some(Severity::Low),
+            _ => None,
+        }
+    }
+
+    fn label(&self) -> ColoredString {
+        match self {
+            Severity::Low => "LOW".bright_blue(),
+            Severity::Moderate => "MODERATE".yellow(),
+            Severity::High => "HIGH".red(),
+            Severity::Critical => "CRITICAL".bright_red().bold(),
+        }
+    }
+}
+
+/// base score of a CVSS v3 vector like `CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H`
+fn cvss3_score(vector: &str) -> Option<f64> {
+    let metrics: HashMap<&str, &str> = vector.split('/').skip(1).filter_map(|m| m.split_once(':')).collect();
+    let changed = *metrics.get("S")? == "C";
+    let av: f64 = match *metrics.get("AV")? { "N" => 0.85, "A" => 0.62, "L" => 0.55, "P" => 0.2, _ => return None };
+    let ac = match *metrics.get("AC")? { "L" => 0.77, "H" => 0.44, _ => return None };
+    let pr = match (*metrics.get("PR")?, changed) {
+        ("N", _) => 0.85,
+        ("L", false) => 0.62,
+        ("L", true) => 0.68,
+        ("H", false) => 0.27,
+        ("H", true) => 0.5,
+        _ => return None,
+    };
+    let ui = match *metrics.get("UI")? { "N" => 0.85, "R" => 0.62, _ => return None };
+    let cia = |key: &str| match metrics.get(key) { Some(&"H") => Some(0.56), Some(&"L") => Some(0.22), Some(&"N") => Some(0.0), _ => None };
+    let iss: f64 = 1.0 - (1.0 - cia("C")?) * (1.0 - cia("I")?) * (1.0 - cia("A")?);
+
+    let impact = if changed { 7.52 * (iss - 0.029) - 3.25 * (iss - 0.02).powi(15) } else { 6.42 * iss };
+    if impact <= 0.0 {
+        return Some(0.0);
+    }
+    let exploitability = 8.22 * av * ac * pr * ui;
+    let base = if changed { 1.08 * (impact + exploitability) } else { impact + exploitability };
+    // CVSS rounds up to one decimal
+    Some((base.min(10.0) * 10.0 - 1e-9).ceil() / 10.0)
+}
+
+/// An OSV advisory affecting one of our packages
+struct Finding {
+    id: String,
+    summary: String,
+    severity: Option<Severity>,
+    fixed: Vec<String>,
+}
+
+impl Finding {
+    fn from_osv(osv: &Value, key: &str) -> Finding {
+        let affected = affected_entries(osv, key);
+
+        // GHSA advisories carry a severity label, otherwise score the CVSS vector
+        let label = affected
+            .iter()
+            .chain(std::iter::once(&osv))
+            .find_map(|v| v["database_specific"]["severity"].as_str())
+            .and_then(Severity::parse);
+        let scored = osv["severity"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|s| s["type"].as_str().map(|t| t.starts_with("CVSS_V3")).unwrap_or(false))
+            .filter_map(|s| s["score"].as_str().and_then(cvss3_score))
+            .fold(None, |max: Option<f64>, s| Some(max.map_or(s, |m| m.max(s))))
+            .and_then(Severity::from_score);
+
+        let mut fixed = vec![];
+        for entry in affected.iter() {
+            for range in entry["ranges"].as_array().into_iter().flatten() {
+                for event in range["events"].as_array().into_iter().flatten() {
+                    if let Some(v) = event["fixed"].as_str() {
+                        if !fixed.contains(&v.to_string()) {
+                            fixed.push(v.to_string());
+                        }
+                    }
+                }
+            }
+        }
+
+        let summary = osv["summary"].as_str().or_else(|| osv["details"].as_str()).unwrap_or("");
+        Finding {
+            id: osv["id"].as_str().unwrap_or("UNKNOWN").to_string(),
+            summary: summary.lines().next().unwrap_or("").to_string(),
+            severity: label.or(scored),
+            fixed,
+        }
+    }
+}
+
+/// the `affected` entries of an advisory that are about this PyPI package
+fn affected_entries<'a>(osv: &'a Value, key: &str) -> Vec<&'a Value> {
+    osv["affected"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|a| a["package"]["ecosystem"].as_str() == Some("PyPI"))
+        .filter(|a| a["package"]["name"].as_str().map(normalize_name).as_deref() == Some(key))
+        .collect()
+}
+
+/// true if `version` falls in one of the advisory's affected versions or ranges
+fn is_affected(osv: &Value, key: &str, version: &str) -> bool {
+    let parsed = Version::parse(version);
+    for entry in affected_entries(osv, key) {
+        if entry["versions"].as_array().into_iter().flatten().any(|v| v.as_str() == Some(version)) {
+            return true;
+        }
+        let parsed = match &parsed {
+            Some(v) => v,
+            None => continue,
+        };
+        for range in entry["ranges"].as_array().into_iter().flatten() {
+            if range["type"].as_str() == Some("GIT") {
+                continue;
+            }
+            let mut events: Vec<(Option<Version>, &str)> = range["events"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|e| {
+                    let (kind, value) = e.as_object()?.iter().next()?;
+                    let value = value.as_str()?;
+                    // "0" means every version
+                    let version = if value == "0" { None } else { Some(Version::parse(value)?) };
+                    Some((version, kind.as_str()))
+                })
+                .collect();
+            events.sort_by(|a, b| a.0.cmp(&b.0));
+
+            let mut affected = false;
+            for (at, kind) in events.iter() {
+                let reached = at.as_ref().map(|at| parsed >= at).unwrap_or(true);
+                match *kind {
+                    "introduced" if reached => affected = true,
+                    "fixed" if reached => affected = false,
+                    "last_affected" if at.as_ref().map(|at| parsed > at).unwrap_or(false) => affected = false,
+                    _ => {},
+                }
+            }
+            if affected {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Where advisories come from: an OSV dump on disk or an OSV API server
+enum Database {
+    Local(HashMap<String, Vec<Value>>),
+    Remote(String),
+}
+
+impl Database {
+    /// index a directory of OSV json files, or the `all.zip` OSV publishes per ecosystem
+    fn load_local(path: &Path) -> Result<Database, String> {
+        let mut documents = vec![];
+        if path.is_dir() {
+            let entries = fs::read_dir(path).map_err(|e| e.to_string())?;
+            for entry in entries.flatten() {
+                if entry.path().extension().map(|e| e == "json").unwrap_or(false) {
+                    documents.push(fs::read_to_string(entry.path()).map_err(|e| e.to_string())?);
+                }
+            }
+        } else {
+            let file = fs::File::open(path).map_err(|e| e.to_string())?;
+            let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+            for i in 0..archive.len() {
+                let mut entry = archive.by_index(i).map_err(|e| e.to_string())?;
+                if !entry.name().ends_with(".json") {
+                    continue;
+                }
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).map_err(|e| e.to_string())?;
+                documents.push(contents);
+            }
+        }
+
+        let mut advisories: HashMap<String, Vec<Value>> = HashMap::new();
+        for doc in documents.iter() {
+            let osv: Value = match serde_json::from_str(doc) {
+                Ok(osv) => osv,
+                Err(_) => continue,
+            };
+            if !osv["withdrawn"].is_null() {
+                continue;
+            }
+            let mut keys: Vec<String> = osv["affected"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|a| a["package"]["ecosystem"].as_str() == Some("PyPI"))
+                .filter_map(|a| a["package"]["name"].as_str().map(normalize_name))
+                .collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                advisories.entry(key).or_default().push(osv.clone());
+            }
+        }
+        Ok(Database::Local(advisories))
+    }
+
+    fn findings(&self, name: &str, version: &str) -> Result<Vec<Finding>, String> {
+        let key = normalize_name(name);
+        match self {
+            Database::Local(advisories) => Ok(advisories
+                .get(&key)
+                .map(|list| list.iter().filter(|osv| is_affected(osv, &key, version)).map(|osv| Finding::from_osv(osv, &key)).collect())
+                .unwrap_or_default()),
+            Database::Remote(url) => {
+                let query = serde_json::json!({
+                    "version": version,
+                    "package": { "name": name, "ecosystem": "PyPI" },
+                });
+                let client = reqwest::blocking::Client::new();
+                let resp = client.post(format!("{}/v1/query", url)).json(&query).send().map_err(|e| e.to_string())?;
+                if !resp.status().is_success() {
+                    return Err(format!("advisory server returned {}", resp.status()));
+                }
+                let json: Value = resp.json().map_err(|e| e.to_string())?;
+                // the server already matched the version
+                Ok(json["vulns"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter(|osv| osv["withdrawn"].is_null())
+                    .map(|osv| Finding::from_osv(osv, &key))
+                    .collect())
+            },
+        }
+    }
+}
+
+/// the packages to audit: everything in ppm.lock plus pinned packages it doesn't know about
+fn inventory(conf: &Config) -> Vec<(String, String)> {
+    let mut packages: Vec<(String, String)> = match LockFile::load_from_file(LOCK_FILE) {
+        Ok(lock) => lock.package.iter().map(|p| (p.name.clone(), p.version.clone())).collect(),
+        Err(_) => vec![],
+    };
+    for req in conf.lock_requirements() {
+        if packages.iter().any(|(n, _)| normalize_name(n) == req.key()) {
+            continue;
+        }
+        match req.specifier.strip_prefix("==") {
+            Some(version) => packages.push((req.name.clone(), version.to_string())),
+            None => wprint(format!("Skipping '{}', it has no pinned version", req.name)),
+        }
+    }
+    packages.sort_by_key(|(name, _)| normalize_name(name));
+    packages
+}
+
+
+#[derive(Args, Debug)]
+pub struct AuditPackages {
+
+    /// OSV advisories to check against, a directory of json files or an OSV all.zip
+    #[clap(long = "db")]
+    pub db: Option<String>,
+
+    /// Base url of an OSV API server or mirror
+    #[clap(long = "url")]
+    pub url: Option<String>,
+
+    /// Fail when an advisory is at least this severe: low, moderate, high, critical or none
+    #[clap(long = "fail-on", default_value = "low")]
+    pub fail_on: String,
+
+}
+
+impl AuditPackages {
+
+    fn database(&self) -> Result<Database, String> {
+        let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        if let Some(db) = self.db.clone().or_else(|| env("PPM_AUDIT_DB")) {
+            return Database::load_local(Path::new(&db)).map_err(|e| format!("Could not read advisories from {}: {}", db, e));
+        }
+        let url = self.url.clone().or_else(|| env("PPM_AUDIT_URL")).unwrap_or_else(|| OSV_URL.to_string());
+        Ok(Database::Remote(url.trim_end_matches('/').to_string()))
+    }
+
+    /// returns false if the audit failed or found advisories at or above `--fail-on`
+    fn audit(&self) -> bool {
+        let threshold = match self.fail_on.to_lowercase().as_str() {
+            "none" => None,
+            level => match Severity::parse(level) {
+                Some(severity) => Some(severity),
+                None => {
+                    eprint(format!("Invalid severity '{}', use low, moderate, high, critical or none", self.fail_on));
+                    return false;
+                }
+            },
+        };
+
+        if !Path::new("project.toml").exists() {
+            eprint("Could not find project.toml".to_owned());
+            return false;
+        }
+
+        let conf = match Config::load_from_file("project.toml") {
+            Ok(conf) => conf,
+            Err(e) => {
+                eprint(e.to_string());
+                return false;
+            }
+        };
+
+        let db = match self.database() {
+            Ok(db) => db,
+            Err(e) => {
+                eprint(e);
+                return false;
+            }
+        };
+
+        let packages = inventory(&conf);
+        let mut found = 0;
+        let mut affected = 0;
+        let mut failing = false;
+        for (name, version) in packages.iter() {
+            let findings = match db.findings(name, version) {
+                Ok(findings) => findings,
+                Err(e) => {
+                    eprint(format!("Could not check {} {}: {}", name, version, e));
+                    return false;
+                }
+            };
+            if findings.is_empty() {
+                continue;
+            }
+
+            affected += 1;
+            println!("{} {}", name.bright_cyan().bold(), version.bright_yellow());
+            for finding in findings.iter() {
+                found += 1;
+                // advisories without a severity always count
+                failing |= match (threshold, finding.severity) {
+                    (None, _) => false,
+                    (Some(min), Some(severity)) => severity >= min,
+                    (Some(_), None) => true,
+                };
+                let severity = finding.severity.map(|s| s.label()).unwrap_or_else(|| "UNKNOWN".normal());
+                let fixed = if finding.fixed.is_empty() { "no fix available".to_string() } else { format!("fixed in {}", finding.fixed.join(", ")) };
+                println!("  {}  {}  {}", finding.id.bold(), severity, fixed.green());
+                if !finding.summary.is_empty() {
+                    println!("    {}", finding.summary);
+                }
+            }
+        }
+
+        if found == 0 {
+            iprint(format!("No known vulnerabilities found in {} packages", packages.len()));
+        } else {
+            wprint(format!("Found {} vulnerabilities in {} of {} packages", found, affected, packages.len()));
+        }
+        !failing
+    }
+
+    pub fn run(&self) {
+        if !self.audit() {
+            std::process::exit(1);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn advisory(affected: Value) -> Value {
+        json!({ "id": "GHSA-test", "summary": "Something bad\nmore details", "affected": affected })
+    }
+
+    fn ranges(events: Value) -> Value {
+        advisory(json!([{ "package": { "ecosystem": "PyPI", "name": "Some_Pkg" }, "ranges": [{ "type": "ECOSYSTEM", "events": events }] }]))
+    }
+
+    #[test]
+    fn scores_cvss3_vectors() {
+        assert_eq!(cvss3_score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"), Some(9.8));
+        assert_eq!(cvss3_score("CVSS:3.1/AV:N/AC:L/PR:N/UI:R/S:C/C:L/I:L/A:N"), Some(6.1));
+        assert_eq!(cvss3_score("CVSS:3.0/AV:L/AC:L/PR:L/UI:N/S:U/C:H/I:H/A:H"), Some(7.8));
+        assert_eq!(cvss3_score("CVSS:3.1/AV:N/AC:H/PR:N/UI:N/S:U/C:H/I:N/A:N"), Some(5.9));
+        assert_eq!(cvss3_score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:N/I:N/A:N"), Some(0.0));
+        assert_eq!(cvss3_score("CVSS:3.1/AV:X/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"), None);
+        assert_eq!(cvss3_score("CVSS:3.1/AV:N"), None);
+    }
+
+    #[test]
+    fn maps_scores_and_labels_to_severities() {
+        assert_eq!(Severity::from_score(9.8), Some(Severity::Critical));
+        assert_eq!(Severity::from_score(7.0), Some(Severity::High));
+        assert_eq!(Severity::from_score(6.1), Some(Severity::Moderate));
+        assert_eq!(Severity::from_score(0.1), Some(Severity::Low));
+        assert_eq!(Severity::from_score(0.0), None);
+        assert_eq!(Severity::parse("MEDIUM"), Some(Severity::Moderate));
+        assert_eq!(Severity::parse("unknown"), None);
+    }
+
+    #[test]
+    fn matches_introduced_and_fixed_ranges() {
+        let osv = ranges(json!([{ "introduced": "0" }, { "fixed": "1.2" }, { "introduced": "2.0" }, { "fixed": "2.1.3" }]));
+        assert!(is_affected(&osv, "some-pkg", "0.1"));
+        assert!(is_affected(&osv, "some-pkg", "1.2rc1"));
+        assert!(!is_affected(&osv, "some-pkg", "1.2"));
+        assert!(!is_affected(&osv, "some-pkg", "1.9"));
+        assert!(is_affected(&osv, "some-pkg", "2.1"));
+        assert!(!is_affected(&osv, "some-pkg", "2.1.3"));
+        assert!(!is_affected(&osv, "other-pkg", "0.1"));
+    }
+
+    #[test]
+    fn matches_last_affected_and_open_ranges() {
+        let osv = ranges(json!([{ "introduced": "1.0" }, { "last_affected": "1.4" }]));
+        assert!(!is_affected(&osv, "some-pkg", "0.9"));
+        assert!(is_affected(&osv, "some-pkg", "1.4"));
+        assert!(!is_affected(&osv, "some-pkg", "1.4.1"));
+
+        let osv = ranges(json!([{ "introduced": "3.0" }]));
+        assert!(is_affected(&osv, "some-pkg", "99"));
+        assert!(!is_affected(&osv, "some-pkg", "2.9"));
+    }
+
+    #[test]
+    fn matches_listed_versions_and_skips_git_ranges() {
+        let osv = advisory(json!([{
+            "package": { "ecosystem": "PyPI", "name": "some-pkg" },
+            "versions": ["1.0", "1.1"],
+            "ranges": [{ "type": "GIT", "events": [{ "introduced": "0" }] }],
+        }]));
+        assert!(is_affected(&osv, "some-pkg", "1.1"));
+        assert!(!is_affected(&osv, "some-pkg", "1.2"));
+
+        let npm = json!({ "affected": [{ "package": { "ecosystem": "npm", "name": "some-pkg" }, "versions": ["1.0"] }] });
+        assert!(!is_affected(&npm, "some-pkg", "1.0"));
+    }
+
+    #[test]
+    fn builds_findings_from_advisories() {
+        let mut osv = ranges(json!([{ "introduced": "0" }, { "fixed": "1.2" }, { "introduced": "2.0" }, { "fixed": "2.1.3" }]));
+        osv["severity"] = json!([{ "type": "CVSS_V3", "score": "CVSS:3.1/AV:N/AC:L/PR:N/UI:R/S:C/C:L/I:L/A:N" }]);
+        let finding = Finding::from_osv(&osv, "some-pkg");
+        assert_eq!(finding.id, "GHSA-test");
+        assert_eq!(finding.summary, "Something bad");
+        assert_eq!(finding.severity, Some(Severity::Moderate));
+        assert_eq!(finding.fixed, vec!["1.2", "2.1.3"]);
+
+        // a GHSA label wins over the score
+        osv["database_specific"] = json!({ "severity": "HIGH" });
+        assert_eq!(Finding::from_osv(&osv, "some-pkg").severity, Some(Severity::High));
+    }
+
+    #[test]
+    fn indexes_each_advisory_once_per_package() {
+        let dir = std::env::temp_dir().join(format!("ppm-test-{}-osv", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let osv = json!({ "id": "PYSEC-1", "affected": [
+            { "package": { "ecosystem": "PyPI", "name": "a-pkg" }, "versions": ["1.0"] },
+            { "package": { "ecosystem": "PyPI", "name": "b-pkg" }, "versions": ["1.0"] },
+            { "package": { "ecosystem": "PyPI", "name": "A_Pkg" }, "versions": ["2.0"] },
+        ] });
+        fs::write(dir.join("PYSEC-1.json"), osv.to_string()).unwrap();
+        fs::write(dir.join("withdrawn.json"), json!({ "id": "PYSEC-2", "withdrawn": "2024-01-01", "affected": osv["affected"] }).to_string()).unwrap();
+
+        let db = Database::load_local(&dir).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        match &db {
+            Database::Local(advisories) => {
+                assert_eq!(advisories["a-pkg"].len(), 1);
+                assert_eq!(advisories["b-pkg"].len(), 1);
+            },
+            Database::Remote(_) => unreachable!(),
+        }
+        assert_eq!(db.findings("A.Pkg", "2.0").unwrap().len(), 1);
+        assert!(db.findings("a-pkg", "3.0").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,354 @@
+use clap::{Args, Subcommand};
+use colored::*;
+use std::{fs, io, path::{Path, PathBuf}, time::{Duration, SystemTime}};
+use crate::index::Artifact;
+use crate::utils::*;
+use crate::wheel::sha256_hex;
+
+/// Content addressed store for downloaded and built artifacts, shared by all projects
+///
+/// ```text
+/// <cache>/blobs/sha256/ab/abcdef...        artifact contents, named by their hash
+/// <cache>/built/<sdist sha256>/<wheel>     hash of a wheel built from that sdist
+/// ```
+pub struct Cache {
+    root: PathBuf,
+}
+
+impl Cache {
+    /// the cache under the user cache dir, or `PPM_CACHE_DIR` if set
+    pub fn open() -> Option<Cache> {
+        let root = match std::env::var("PPM_CACHE_DIR") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => dirs::cache_dir()?.join("ppm"),
+        };
+        fs::create_dir_all(root.join("blobs").join("sha256")).ok()?;
+        fs::create_dir_all(root.join("built")).ok()?;
+        Some(Cache { root })
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        let sha256 = sha256.to_lowercase();
+        self.root.join("blobs").join("sha256").join(&sha256[..2.min(sha256.len())]).join(&sha256)
+    }
+
+    /// the artifact with this hash, if it is cached
+    pub fn get(&self, sha256: &str) -> Option<Vec<u8>> {
+        let path = self.blob_path(sha256);
+        let data = fs::read(&path).ok()?;
+        // a corrupted blob is treated as a miss
+        if sha256_hex(&data) != sha256.to_lowercase() {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        // mark as used so `prune` keeps it
+        if let Ok(file) = fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(data)
+    }
+
+    /// store an artifact, returning its hash
+    pub fn put(&self, data: &[u8]) -> io::Result<String> {
+        let sha256 = sha256_hex(data);
+        let path = self.blob_path(&sha256);
+        if !path.exists() {
+            fs::create_dir_all(path.parent().unwrap())?;
+            // write under a temp name so other ppm processes never read half a blob
+            let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+            fs::write(&tmp, data)?;
+            fs::rename(&tmp, &path)?;
+        }
+        Ok(sha256)
+    }
+
+    fn built_dir(&self, sdist_sha256: &str) -> PathBuf {
+        self.root.join("built").join(sdist_sha256.to_lowercase())
+    }
+
+    /// wheels previously built from the sdist with this hash, as artifacts pointing into the cache
+    pub fn built_wheels(&self, sdist_sha256: &str) -> Vec<Artifact> {
+        let entries = match fs::read_dir(self.built_dir(sdist_sha256)) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        entries
+            .flatten()
+            .filter_map(|e| {
+                let sha256 = fs::read_to_string(e.path()).ok()?.trim().to_string();
+                let blob = self.blob_path(&sha256);
+                if !blob.exists() {
+                    return None;
+                }
+                Some(Artifact {
+                    filename: e.file_name().to_string_lossy().to_string(),
+                    url: blob.to_string_lossy().to_string(),
+                    sha256: Some(sha256),
+                })
+            })
+            .collect()
+    }
+
+    pub fn store_built_wheel(&self, sdist_sha256: &str, filename: &str, data: &[u8]) -> io::Result<()> {
+        let sha256 = self.put(data)?;
+        let dir = self.built_dir(sdist_sha256);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(filename), sha256)
+    }
+}
+
+fn dir_stats(dir: &Path) -> (u64, u64) {
+    let mut files = 0;
+    let mut size = 0;
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                let (f, s) = dir_stats(&path);
+                files += f;
+                size += s;
+            } else if let Ok(meta) = entry.metadata() {
+                files += 1;
+                size += meta.len();
+            }
+        }
+    }
+    (files, size)
+}
+
+fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} {}", bytes, units[0]) } else { format!("{:.1} {}", size, units[unit]) }
+}
+
+/// parse durations like `30d`, `12h`, `2w`
+fn parse_duration(input: &str) -> Option<Duration> {
+    let input = input.trim();
+    let split = input.find(|c: char| !c.is_ascii_digit())?;
+    let amount: u64 = input[..split].parse().ok()?;
+    let secs = match &input[split..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        "w" => 60 * 60 * 24 * 7,
+        _ => return None,
+    };
+    Some(Duration::from_secs(amount * secs))
+}
+
+/// remove files older than `cutoff` (all of them if `None`) and the empty directories left behind
+fn prune_dir(dir: &Path, cutoff: Option<SystemTime>) -> (u64, u64) {
+    let mut removed = 0;
+    let mut freed = 0;
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                let (r, f) = prune_dir(&path, cutoff);
+                removed += r;
+                freed += f;
+                let _ = fs::remove_dir(&path);
+                continue;
+            }
+            let meta = match entry.metadata() {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+            let stale = match (cutoff, meta.modified()) {
+                (None, _) => true,
+                (Some(cutoff), Ok(modified)) => modified < cutoff,
+                (Some(_), Err(_)) => false,
+            };
+            if stale && fs::remove_file(&path).is_ok() {
+                removed += 1;
+                freed += meta.len();
+            }
+        }
+    }
+    (removed, freed)
+}
+
+
+#[derive(Args, Debug)]
+pub struct CacheCommand {
+    #[clap(subcommand)]
+    pub action: CacheAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheAction {
+    /// Show where the cache is and how big it is
+    Info,
+
+    /// Remove everything from the cache
+    Clean,
+
+    /// Remove cache entries that haven't been used recently
+    Prune(PruneCache),
+}
+
+#[derive(Args, Debug)]
+pub struct PruneCache {
+    /// Remove entries not used for this long, e.g. 30d, 12h, 2w
+    #[clap(long = "older-than", default_value = "30d")]
+    pub older_than: String,
+}
+
+impl CacheCommand {
+    pub fn run(&self) {
+        let cache = match Cache::open() {
+            Some(cache) => cache,
+            None => {
+                eprint("Could not open the ppm cache directory".to_owned());
+                return;
+            }
+        };
+
+        match &self.action {
+            CacheAction::Info => {
+                let (blobs, size) = dir_stats(&cache.root.join("blobs"));
+                let (built, _) = dir_stats(&cache.root.join("built"));
+                println!("{}: {}", "Location".green().bold(), cache.root.display().to_string().bright_cyan());
+                println!("{}: {}", "Artifacts".green().bold(), blobs.to_string().bright_yellow());
+                println!("{}: {}", "Built wheels".green().bold(), built.to_string().bright_yellow());
+                println!("{}: {}", "Size".green().bold(), human_size(size).bright_red());
+            },
+            CacheAction::Clean => {
+                let (removed, freed) = prune_dir(&cache.root, None);
+                iprint(format!("Removed {} files, freed {}", removed, human_size(freed)));
+            },
+            CacheAction::Prune(prune) => {
+                let age = match parse_duration(&prune.older_than) {
+                    Some(age) => age,
+                    None => {
+                        eprint(format!("Invalid duration '{}', use e.g. 30d, 12h or 2w", prune.older_than));
+                        return;
+                    }
+                };
+                let cutoff = SystemTime::now() - age;
+                let (removed, freed) = prune_dir(&cache.root.join("blobs"), Some(cutoff));
+                // built wheel entries whose blob is gone are useless now
+                let dangling = prune_dangling(&cache, &cache.root.join("built"));
+                iprint(format!("Removed {} files, freed {}", removed + dangling, human_size(freed)));
+            },
+        }
+    }
+}
+
+fn prune_dangling(cache: &Cache, dir: &Path) -> u64 {
+    let mut removed = 0;
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                removed += prune_dangling(cache, &path);
+                let _ = fs::remove_dir(&path);
+                continue;
+            }
+            let sha256 = fs::read_to_string(&path).unwrap_or_default();
+            if !cache.blob_path(sha256.trim()).is_file() && fs::remove_file(&path).is_ok() {
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_cache(name: &str) -> Cache {
+        let root = std::env::temp_dir().join(format!("ppm-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("blobs").join("sha256")).unwrap();
+        fs::create_dir_all(root.join("built")).unwrap();
+        Cache { root }
+    }
+
+    fn age(cache: &Cache, sha256: &str, days: u64) {
+        let file = fs::File::options().append(true).open(cache.blob_path(sha256)).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60)).unwrap();
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30d"), Some(Duration::from_secs(30 * 24 * 60 * 60)));
+        assert_eq!(parse_duration(" 2w "), Some(Duration::from_secs(14 * 24 * 60 * 60)));
+        assert_eq!(parse_duration("12h"), Some(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30"), None);
+        assert_eq!(parse_duration("d"), None);
+        assert_eq!(parse_duration("3y"), None);
+        assert_eq!(parse_duration("1.5d"), None);
+    }
+
+    #[test]
+    fn stores_and_verifies_blobs() {
+        let cache = temp_cache("blobs");
+        let sha256 = cache.put(b"wheel").unwrap();
+        assert_eq!(sha256, sha256_hex(b"wheel"));
+        assert_eq!(cache.get(&sha256.to_uppercase()).as_deref(), Some(&b"wheel"[..]));
+
+        // a corrupted blob is a miss and gets dropped
+        fs::write(cache.blob_path(&sha256), b"tampered").unwrap();
+        assert_eq!(cache.get(&sha256), None);
+        assert!(!cache.blob_path(&sha256).exists());
+        let _ = fs::remove_dir_all(&cache.root);
+    }
+
+    #[test]
+    fn lists_built_wheels_whose_blob_exists() {
+        let cache = temp_cache("built");
+        cache.store_built_wheel("ABC123", "pkg-1.0-py3-none-any.whl", b"built").unwrap();
+        let wheels = cache.built_wheels("abc123");
+        assert_eq!(wheels.len(), 1);
+        assert_eq!(wheels[0].filename, "pkg-1.0-py3-none-any.whl");
+        assert_eq!(wheels[0].sha256.as_deref(), Some(sha256_hex(b"built").as_str()));
+        assert_eq!(wheels[0].url, cache.blob_path(&sha256_hex(b"built")).to_string_lossy());
+
+        fs::remove_file(cache.blob_path(&sha256_hex(b"built"))).unwrap();
+        assert!(cache.built_wheels("abc123").is_empty());
+        assert!(cache.built_wheels("unknown").is_empty());
+        let _ = fs::remove_dir_all(&cache.root);
+    }
+
+    #[test]
+    fn prunes_expired_blobs_and_dangling_built_wheels() {
+        let cache = temp_cache("prune");
+        let fresh = cache.put(b"fresh").unwrap();
+        let expired = cache.put(b"expired").unwrap();
+        let used = cache.put(b"used").unwrap();
+        cache.store_built_wheel("sdist", "fresh-1.0-py3-none-any.whl", b"fresh").unwrap();
+        cache.store_built_wheel("sdist", "expired-1.0-py3-none-any.whl", b"expired").unwrap();
+        age(&cache, &expired, 40);
+        age(&cache, &fresh, 10);
+        // reading a blob counts as using it
+        age(&cache, &used, 40);
+        cache.get(&used).unwrap();
+
+        let cutoff = SystemTime::now() - parse_duration("30d").unwrap();
+        assert_eq!(prune_dir(&cache.root.join("blobs"), Some(cutoff)), (1, 7));
+        assert!(cache.blob_path(&fresh).is_file());
+        assert!(cache.blob_path(&used).is_file());
+        assert!(!cache.blob_path(&expired).exists());
+
+        assert_eq!(prune_dangling(&cache, &cache.root.join("built")), 1);
+        let wheels: Vec<String> = cache.built_wheels("sdist").into_iter().map(|w| w.filename).collect();
+        assert_eq!(wheels, vec!["fresh-1.0-py3-none-any.whl"]);
+        assert!(!cache.built_dir("sdist").join("expired-1.0-py3-none-any.whl").exists());
+
+        // cleaning removes everything
+        let (removed, _) = prune_dir(&cache.root, None);
+        assert_eq!(removed, 3);
+        assert_eq!(dir_stats(&cache.root), (0, 0));
+        let _ = fs::remove_dir_all(&cache.root);
+    }
+}
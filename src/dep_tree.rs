@@ -0,0 +1,263 @@
+use clap::Args;
+use colored::*;
+use std::{path::Path, collections::{HashMap, HashSet}};
+use crate::lockfile::{LockFile, LOCK_FILE};
+use crate::markers::MarkerEnv;
+use crate::metadata::installed_distributions;
+use crate::requirement::{normalize_name, Requirement};
+use crate::settings::Config;
+use crate::utils::*;
+
+/// A dependency edge, the specifier is what the parent requires of the child
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub key: String,
+    pub name: String,
+    pub specifier: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    pub version: String,
+    pub deps: Vec<Edge>,
+}
+
+pub struct DependencyGraph {
+    pub nodes: HashMap<String, Node>,
+    pub roots: Vec<Edge>,
+}
+
+impl DependencyGraph {
+
+    /// build the graph from the metadata of the packages installed in the venv
+    pub fn from_installed(conf: &Config) -> Result<DependencyGraph, ()> {
+        let dists = installed_distributions()?;
+        let env = match MarkerEnv::from_python(&venv_python()) {
+            Ok(env) => Some(env),
+            Err(e) => {
+                wprint(format!("Could not inspect the python interpreter, showing every installed dependency: {}", e));
+                None
+            }
+        };
+
+        // the extras asked for each package, by the project or by other packages
+        let mut extras: HashMap<String, HashSet<String>> = HashMap::new();
+        let requested = conf.install_requirements().into_iter().chain(dists.values().flat_map(|d| d.requires_dist()));
+        for req in requested {
+            extras.entry(req.key()).or_default().extend(req.extras.iter().cloned());
+        }
+
+        let mut nodes = HashMap::new();
+        for (key, dist) in dists.iter() {
+            let mut deps = vec![];
+            let requested = extras.get(key).cloned().unwrap_or_default();
+            for req in dist.requires_dist() {
+                let applies = match (&req.marker, &env) {
+                    (None, _) => true,
+                    (Some(marker), Some(env)) if req.is_extra_only() => requested.iter().any(|e| env.evaluate(marker, Some(e))),
+                    (Some(marker), Some(env)) => env.evaluate(marker, None),
+                    // without an interpreter to ask, what got installed is the best guess
+                    (Some(_), None) => !req.is_extra_only() && dists.contains_key(&req.key()),
+                };
+                if !applies {
+                    continue;
+                }
+                deps.push(Edge {
+                    key: req.key(),
+                    name: req.name.clone(),
+                    specifier: req.specifier.clone(),
+                });
+            }
+            nodes.insert(key.clone(), Node {
+                name: dist.name.clone(),
+                version: dist.version.clone(),
+                deps,
+            });
+        }
+
+        Ok(DependencyGraph {
+            nodes,
+            roots: Self::roots_from_config(conf),
+        })
+    }
+
+    /// build the graph from ppm.lock, used when there is no venv to inspect
+    pub fn from_lock(conf: &Config, lock: &LockFile) -> DependencyGraph {
+        let mut nodes = HashMap::new();
+        for (key, pkg) in lock.packages_by_key() {
+            let deps = pkg.dependencies
+                .iter()
+                .filter_map(|d| Requirement::parse(d))
+                .map(|req| Edge {
+                    key: req.key(),
+                    name: req.name.clone(),
+                    specifier: req.specifier.clone(),
+                })
+                .collect();
+            nodes.insert(key, Node {
+                name: pkg.name.clone(),
+                version: pkg.version.clone(),
+                deps,
+            });
+        }
+        DependencyGraph {
+            nodes,
+            roots: Self::roots_from_config(conf),
+        }
+    }
+
+    fn roots_from_config(conf: &Config) -> Vec<Edge> {
+        conf.install_requirements()
+            .into_iter()
+            .map(|req| Edge {
+                key: req.key(),
+                name: req.name.clone(),
+                specifier: req.specifier,
+            })
+            .collect()
+    }
+
+    fn print_edge(&self, edge: &Edge, prefix: &str, is_last: bool, visited: &mut HashSet<String>) {
+        let branch = if is_last { "└── " } else { "├── " };
+        let spec = if edge.specifier.is_empty() { "*".to_owned() } else { edge.specifier.clone() };
+
+        let node = match self.nodes.get(&edge.key) {
+            Some(node) => node,
+            None => {
+                println!("{}{}{} [required: {}] {}", prefix, branch, edge.name.bright_yellow().bold(), spec.bright_cyan(), "(not installed)".bright_red());
+                return;
+            }
+        };
+
+        // a package already printed on this path means a cycle
+        if visited.contains(&edge.key) {
+            println!("{}{}{}=={} [required: {}] {}", prefix, branch, node.name.bright_yellow().bold(), node.version.bright_red(), spec.bright_cyan(), "(cycle)".bright_red());
+            return;
+        }
+        println!("{}{}{}=={} [required: {}]", prefix, branch, node.name.bright_yellow().bold(), node.version.bright_red(), spec.bright_cyan());
+
+        visited.insert(edge.key.clone());
+        let child_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+        let mut deps = node.deps.clone();
+        deps.sort_by(|a, b| a.key.cmp(&b.key));
+        for (i, dep) in deps.iter().enumerate() {
+            self.print_edge(dep, &child_prefix, i == deps.len() - 1, visited);
+        }
+        visited.remove(&edge.key);
+    }
+
+    pub fn print_tree(&self, project_name: &str) {
+        println!("{}", project_name.bright_cyan().bold());
+        let mut visited = HashSet::new();
+        for (i, root) in self.roots.iter().enumerate() {
+            self.print_edge(root, "", i == self.roots.len() - 1, &mut visited);
+        }
+    }
+
+    /// every path from a top-level package down to `target`
+    pub fn paths_to(&self, target: &str) -> Vec<Vec<Edge>> {
+        let mut paths = vec![];
+        let mut current = vec![];
+        for root in self.roots.iter() {
+            self.collect_paths(root, target, &mut current, &mut paths);
+        }
+        paths
+    }
+
+    fn collect_paths(&self, edge: &Edge, target: &str, current: &mut Vec<Edge>, paths: &mut Vec<Vec<Edge>>) {
+        if current.iter().any(|e| e.key == edge.key) {
+            return;
+        }
+        current.push(edge.clone());
+        if edge.key == target {
+            paths.push(current.clone());
+        } else if let Some(node) = self.nodes.get(&edge.key) {
+            for dep in node.deps.iter() {
+                self.collect_paths(dep, target, current, paths);
+            }
+        }
+        current.pop();
+    }
+}
+
+fn load_graph() -> Result<(Config, DependencyGraph), ()> {
+    if !Path::new("project.toml").exists() {
+        eprint("Could not find project.toml".to_owned());
+        return Err(());
+    }
+    let conf = match Config::load_from_file("project.toml") {
+        Ok(conf) => conf,
+        Err(e) => {
+            eprint(e.to_string());
+            return Err(());
+        }
+    };
+    if venv_site_packages().is_none() {
+        if let Ok(lock) = LockFile::load_from_file(LOCK_FILE) {
+            let graph = DependencyGraph::from_lock(&conf, &lock);
+            return Ok((conf, graph));
+        }
+    }
+    let graph = DependencyGraph::from_installed(&conf)?;
+    Ok((conf, graph))
+}
+
+pub fn show_dependency_tree() {
+    let (conf, graph) = match load_graph() {
+        Ok(loaded) => loaded,
+        Err(_) => return,
+    };
+    if graph.roots.is_empty() {
+        wprint("No packages in project.toml".to_owned());
+        return;
+    }
+    graph.print_tree(&conf.project.name);
+}
+
+
+#[derive(Args, Debug)]
+pub struct WhyPackage {
+
+    /// Package to explain
+    pub pkg_name: String,
+
+}
+
+impl WhyPackage {
+    pub fn explain(&self) {
+        let (_, graph) = match load_graph() {
+            Ok(loaded) => loaded,
+            Err(_) => return,
+        };
+        let target = normalize_name(&self.pkg_name);
+        let version = match graph.nodes.get(&target) {
+            Some(node) => node.version.clone(),
+            None => {
+                eprint(format!("Package '{}' is not installed", self.pkg_name));
+                return;
+            }
+        };
+
+        let paths = graph.paths_to(&target);
+        if paths.is_empty() {
+            wprint(format!("'{}' is installed but not required by any project package", self.pkg_name));
+            return;
+        }
+
+        println!("{}=={} is required by:", self.pkg_name.bright_yellow().bold(), version.bright_red());
+        for path in paths.iter() {
+            let steps = path
+                .iter()
+                .map(|e| {
+                    if e.specifier.is_empty() {
+                        e.name.bright_yellow().to_string()
+                    } else {
+                        format!("{} ({})", e.name.bright_yellow(), e.specifier.bright_cyan())
+                    }
+                })
+                .collect::<Vec<String>>();
+            println!("  {}", steps.join(" -> "));
+        }
+    }
+}
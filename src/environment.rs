@@ -0,0 +1,341 @@
+use clap::Args;
+use colored::*;
+use std::{collections::BTreeMap, fs, path::Path, process::Command, sync::OnceLock};
+use crate::settings::{self, Config};
+use crate::utils::*;
+
+/// names that hint at a value `ppm env` shouldn't print
+const SECRET_HINTS: [&str; 8] = ["SECRET", "TOKEN", "PASSWORD", "PASSWD", "PRIVATE", "CREDENTIAL", "AUTH", "KEY"];
+
+fn is_var_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A variable as written, before `${...}` is expanded
+#[derive(Debug, Clone)]
+struct RawVar {
+    value: String,
+    /// where it was defined, `[env]` or a file name
+    source: String,
+}
+
+/// the value of a quoted .env entry, reading more lines while the quote is open
+fn quoted_value<'a>(first: &str, quote: char, lines: &mut impl Iterator<Item = (usize, &'a str)>) -> Option<String> {
+    let mut text = first.to_string();
+    loop {
+        let mut value = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                c if c == quote => return Some(value),
+                '\\' if quote == '"' => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('$') => value.push_str("$$"),
+                    Some(c) => value.push(c),
+                    None => value.push('\\'),
+                },
+                // single quoted values are taken literally
+                '$' if quote == '\'' => value.push_str("$$"),
+                c => value.push(c),
+            }
+        }
+        let (_, next) = lines.next()?;
+        text = format!("{}\n{}", text, next);
+    }
+}
+
+/// parse a .env file: `KEY=value` lines, optionally `export`ed, with `#` comments and quoting
+fn parse_dotenv(contents: &str, source: &str) -> Result<Vec<(String, RawVar)>, String> {
+    let mut vars = vec![];
+    let mut lines = contents.lines().enumerate();
+    while let Some((number, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line.split_once('=').ok_or_else(|| format!("{} line {}: expected KEY=value", source, number + 1))?;
+        let key = key.trim();
+        if !is_var_name(key) {
+            return Err(format!("{} line {}: invalid variable name '{}'", source, number + 1, key));
+        }
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => quoted_value(&value[1..], quote, &mut lines)
+                .ok_or_else(|| format!("{} line {}: unterminated {} quote", source, number + 1, quote))?,
+            _ => value.split(" #").next().unwrap_or("").trim().to_string(),
+        };
+        vars.push((key.to_string(), RawVar { value, source: source.to_string() }));
+    }
+    Ok(vars)
+}
+
+/// Expands `${VAR}`, `${VAR:-default}` and `$VAR` in project variables
+struct Expander<'a> {
+    raw: &'a BTreeMap<String, RawVar>,
+    expanded: BTreeMap<String, String>,
+    stack: Vec<String>,
+}
+
+impl<'a> Expander<'a> {
+    /// a project variable, or the inherited one when it's undefined or refers back to itself
+    fn lookup(&mut self, name: &str) -> Option<String> {
+        if let Some(value) = self.expanded.get(name) {
+            return Some(value.clone());
+        }
+        match self.raw.get(name) {
+            Some(raw) if !self.stack.iter().any(|s| s == name) => {
+                self.stack.push(name.to_string());
+                let value = self.expand(&raw.value);
+                self.stack.pop();
+                self.expanded.insert(name.to_string(), value.clone());
+                Some(value)
+            },
+            _ => std::env::var(name).ok(),
+        }
+    }
+
+    fn expand(&mut self, value: &str) -> String {
+        let mut out = String::new();
+        let mut rest = value;
+        while let Some(pos) = rest.find('$') {
+            out.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                out.push('$');
+                rest = after;
+            } else if let Some(inner) = rest.strip_prefix('{') {
+                // find the matching brace, defaults may hold `${...}` themselves
+                let mut depth = 1;
+                let end = inner.char_indices().find(|(_, c)| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {},
+                    }
+                    depth == 0
+                });
+                match end {
+                    Some((end, _)) => {
+                        let (name, default) = match inner[..end].split_once(":-") {
+                            Some((name, default)) => (name, Some(default)),
+                            None => (&inner[..end], None),
+                        };
+                        match (self.lookup(name.trim()), default) {
+                            (Some(value), _) if !value.is_empty() => out.push_str(&value),
+                            (_, Some(default)) => out.push_str(&self.expand(default)),
+                            _ => {},
+                        }
+                        rest = &inner[end + 1..];
+                    },
+                    None => {
+                        out.push_str("${");
+                        rest = inner;
+                    },
+                }
+            } else {
+                let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                if len == 0 || !is_var_name(&rest[..len]) {
+                    out.push('$');
+                } else {
+                    out.push_str(&self.lookup(&rest[..len]).unwrap_or_default());
+                    rest = &rest[len..];
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// The variables a project sets for everything ppm runs: `[env]` and the profile's `env`,
+/// then `.env`, then `.env.<profile>`, each overriding the one before
+#[derive(Debug, Default)]
+pub struct ProjectEnv {
+    /// name to value and where it was defined
+    pub vars: BTreeMap<String, (String, String)>,
+}
+
+impl ProjectEnv {
+    pub fn load(conf: &Config) -> Result<ProjectEnv, String> {
+        let mut raw: BTreeMap<String, RawVar> = BTreeMap::new();
+        for (key, value) in conf.env.iter() {
+            raw.insert(key.clone(), RawVar { value: value.clone(), source: "[env]".to_string() });
+        }
+        if let (Some(name), Some(profile)) = (&conf.profile, conf.active_profile()) {
+            for (key, value) in profile.env.iter() {
+                raw.insert(key.clone(), RawVar { value: value.clone(), source: format!("[profiles.{}.env]", name) });
+            }
+        }
+        let mut files = vec![".env".to_string()];
+        if let Some(profile) = &conf.profile {
+            files.push(format!(".env.{}", profile));
+        }
+        for file in files.iter() {
+            if !Path::new(file).exists() {
+                continue;
+            }
+            let contents = fs::read_to_string(file).map_err(|e| format!("Could not read {}: {}", file, e))?;
+            raw.extend(parse_dotenv(&contents, file)?);
+        }
+
+        let mut expander = Expander { raw: &raw, expanded: BTreeMap::new(), stack: vec![] };
+        let vars = raw
+            .iter()
+            .map(|(key, var)| (key.clone(), (expander.lookup(key).unwrap_or_default(), var.source.clone())))
+            .collect();
+        Ok(ProjectEnv { vars })
+    }
+
+    /// set the project variables on a command
+    pub fn apply(&self, cmd: &mut Command) {
+        cmd.envs(self.vars.iter().map(|(key, (value, _))| (key, value)));
+    }
+}
+
+/// the environment of the project in the current directory, loaded once
+pub fn project_env() -> &'static Result<ProjectEnv, String> {
+    static ENV: OnceLock<Result<ProjectEnv, String>> = OnceLock::new();
+    ENV.get_or_init(|| {
+        if !Path::new("project.toml").exists() {
+            return Ok(ProjectEnv::default());
+        }
+        let conf = Config::load_from_file("project.toml").map_err(|e| e.to_string())?;
+        ProjectEnv::load(&conf)
+    })
+}
+
+/// set the project variables on a command, warning once if they couldn't be loaded
+pub fn apply_project_env(cmd: &mut Command) {
+    static WARNED: OnceLock<()> = OnceLock::new();
+    match project_env() {
+        Ok(env) => env.apply(cmd),
+        Err(e) => {
+            WARNED.get_or_init(|| wprint(format!("Project environment not loaded: {}", e)));
+        },
+    }
+}
+
+pub fn is_secret(name: &str) -> bool {
+    let name = name.to_uppercase();
+    SECRET_HINTS.iter().any(|hint| name.contains(hint))
+}
+
+pub fn mask(value: &str) -> String {
+    if value.is_empty() { String::new() } else { "********".to_string() }
+}
+
+
+#[derive(Args, Debug)]
+pub struct ShowEnv {
+
+    /// Print values that look like secrets instead of masking them
+    #[clap(long = "reveal", takes_value = false)]
+    pub reveal: bool,
+
+}
+
+impl ShowEnv {
+    pub fn show(&self) {
+        if !Path::new("project.toml").exists() {
+            eprint("Could not find project.toml".to_owned());
+            return;
+        }
+
+        let env = match project_env() {
+            Ok(env) => env,
+            Err(e) => {
+                eprint(e.clone());
+                std::process::exit(1);
+            }
+        };
+        if let Some(profile) = settings::active_profile() {
+            iprint(format!("Profile: {}", profile));
+        }
+        let width = env.vars.keys().map(|k| k.len()).max().unwrap_or(0);
+        for (key, (value, source)) in env.vars.iter() {
+            let value = if is_secret(key) && !self.reveal { mask(value) } else { value.clone() };
+            println!("{:width$} = {}  {}", key.bright_cyan(), value, format!("({})", source).bright_black(), width = width);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Vec<(String, String)> {
+        parse_dotenv(contents, ".env").unwrap().into_iter().map(|(k, v)| (k, v.value)).collect()
+    }
+
+    fn pairs(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    /// every variable of a .env file after expansion
+    fn expand(contents: &str) -> BTreeMap<String, String> {
+        let raw: BTreeMap<String, RawVar> = parse_dotenv(contents, ".env").unwrap().into_iter().collect();
+        let mut expander = Expander { raw: &raw, expanded: BTreeMap::new(), stack: vec![] };
+        raw.keys().map(|k| (k.clone(), expander.lookup(k).unwrap_or_default())).collect()
+    }
+
+    #[test]
+    fn parses_plain_and_exported_lines() {
+        let contents = "# comment\n\nexport DEBUG=1\nNAME = my app  # trailing\nURL=http://host/#anchor\nEMPTY=\n";
+        assert_eq!(parse(contents), pairs(&[("DEBUG", "1"), ("NAME", "my app"), ("URL", "http://host/#anchor"), ("EMPTY", "")]));
+    }
+
+    #[test]
+    fn parses_quoted_values() {
+        let contents = "A=\"line\\none\\t\\\"q\\\" # kept\"\nB='no $expansion \\n here'\nC=\"multi\nline\"\nD=\"\\$LITERAL\"\n";
+        assert_eq!(parse(contents), pairs(&[
+            ("A", "line\none\t\"q\" # kept"),
+            ("B", "no $$expansion \\n here"),
+            ("C", "multi\nline"),
+            ("D", "$$LITERAL"),
+        ]));
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        assert_eq!(parse_dotenv("A=1\nnot a var\n", ".env").unwrap_err(), ".env line 2: expected KEY=value");
+        assert_eq!(parse_dotenv("1A=x\n", ".env.prod").unwrap_err(), ".env.prod line 1: invalid variable name '1A'");
+        assert_eq!(parse_dotenv("A=1\nB=\"open\nC=2\n", ".env").unwrap_err(), ".env line 2: unterminated \" quote");
+    }
+
+    #[test]
+    fn expands_references_and_defaults() {
+        let vars = expand("HOST=db\nPORT=\nURL=postgres://${HOST}:${PORT:-5432}/$NAME\nNAME=app\nNESTED=${UNSET_PPM_TEST:-${HOST}-fallback}\n");
+        assert_eq!(vars["URL"], "postgres://db:5432/app");
+        assert_eq!(vars["NESTED"], "db-fallback");
+    }
+
+    #[test]
+    fn keeps_literals_and_escaped_dollars() {
+        let vars = expand("A='$HOME'\nB=cost $$5 and $1\nC=${UNCLOSED\nD=$UNSET_PPM_TEST.\n");
+        assert_eq!(vars["A"], "$HOME");
+        assert_eq!(vars["B"], "cost $5 and $1");
+        assert_eq!(vars["C"], "${UNCLOSED");
+        assert_eq!(vars["D"], ".");
+    }
+
+    #[test]
+    fn self_references_use_the_inherited_value() {
+        let vars = expand("PATH=${PATH}:/opt/bin\nPPM_TEST_A=${PPM_TEST_B}\nPPM_TEST_B=${PPM_TEST_A}x\n");
+        assert_eq!(vars["PATH"], format!("{}:/opt/bin", std::env::var("PATH").unwrap_or_default()));
+        // a cycle stops at the variable that refers back
+        assert_eq!(vars["PPM_TEST_A"], "x");
+        assert_eq!(vars["PPM_TEST_B"], "x");
+    }
+
+    #[test]
+    fn masks_secret_looking_names() {
+        assert!(is_secret("api_token"));
+        assert!(is_secret("AWS_SECRET_ACCESS_KEY"));
+        assert!(!is_secret("LOG_LEVEL"));
+        assert_eq!(mask("hunter2"), "********");
+        assert_eq!(mask(""), "");
+    }
+}
@@ -0,0 +1,218 @@
+use clap::Args;
+use std::path::Path;
+use crate::lockfile::{LockFile, LOCK_FILE};
+use crate::markers::MarkerEnv;
+use crate::ppm_functions::pinned_requirements;
+use crate::requirement::Requirement;
+use crate::settings::{Config, EntryTarget};
+use crate::utils::*;
+
+/// a TOML basic string
+fn toml_str(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+fn toml_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        key.to_string()
+    } else {
+        toml_str(key)
+    }
+}
+
+fn toml_array(values: &[String]) -> String {
+    if values.is_empty() {
+        return "[]".to_string();
+    }
+    let items = values.iter().map(|v| format!("    {},\n", toml_str(v))).collect::<String>();
+    format!("[\n{}]", items)
+}
+
+/// quote a YAML scalar when it would otherwise be misread
+fn yaml_str(value: &str) -> String {
+    let plain = value.chars().all(|c| c.is_ascii_alphanumeric() || "=<>!~.-_*[],; ".contains(c))
+        && !value.starts_with(|c: char| "[]*!".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// `major.minor` of the venv interpreter
+fn python_version() -> Option<String> {
+    MarkerEnv::from_python(&venv_python()).ok()?.python_short_version()
+}
+
+fn pyproject(conf: &Config) -> String {
+    let deps: Vec<String> = conf.requirements().iter().map(|r| r.to_string()).collect();
+    let mut out = String::from("[build-system]\nrequires = [\"setuptools>=61\"]\nbuild-backend = \"setuptools.build_meta\"\n\n");
+    out.push_str("[project]\n");
+    out.push_str(&format!("name = {}\n", toml_str(&conf.project.name)));
+    out.push_str(&format!("version = {}\n", toml_str(&conf.project.version)));
+    if !conf.project.description.is_empty() {
+        out.push_str(&format!("description = {}\n", toml_str(&conf.project.description)));
+    }
+    if let Some(python) = python_version() {
+        out.push_str(&format!("requires-python = {}\n", toml_str(&format!(">={}", python))));
+    }
+    out.push_str(&format!("dependencies = {}\n", toml_array(&deps)));
+
+    let mut groups: Vec<&String> = conf.groups.keys().collect();
+    groups.sort();
+    if !groups.is_empty() {
+        out.push_str("\n[project.optional-dependencies]\n");
+        for group in groups {
+            let reqs: Vec<String> = conf.group_requirements(group).unwrap_or_default().iter().map(|r| r.to_string()).collect();
+            out.push_str(&format!("{} = {}\n", toml_key(group), toml_array(&reqs)));
+        }
+    }
+    out
+}
+
+/// a Pipfile entry, `"==1.0"` or an inline table when extras or markers are involved
+fn pipfile_entry(req: &Requirement) -> String {
+    let version = if req.specifier.is_empty() { "*".to_string() } else { req.specifier.clone() };
+    if req.extras.is_empty() && req.marker.is_none() {
+        return format!("{} = {}\n", toml_key(&req.name), toml_str(&version));
+    }
+    let mut fields = vec![format!("version = {}", toml_str(&version))];
+    if !req.extras.is_empty() {
+        fields.push(format!("extras = [{}]", req.extras.iter().map(|e| toml_str(e)).collect::<Vec<String>>().join(", ")));
+    }
+    if let Some(marker) = &req.marker {
+        fields.push(format!("markers = {}", toml_str(marker)));
+    }
+    format!("{} = {{ {} }}\n", toml_key(&req.name), fields.join(", "))
+}
+
+fn pipfile(conf: &Config) -> String {
+    let mut out = String::from("[[source]]\nurl = \"https://pypi.org/simple\"\nverify_ssl = true\nname = \"pypi\"\n\n");
+    out.push_str("[packages]\n");
+    for req in conf.requirements().iter() {
+        out.push_str(&pipfile_entry(req));
+    }
+    out.push_str("\n[dev-packages]\n");
+    for req in conf.group_requirements("dev").unwrap_or_default().iter() {
+        out.push_str(&pipfile_entry(req));
+    }
+    if let Some(python) = python_version() {
+        out.push_str(&format!("\n[requires]\npython_version = {}\n", toml_str(&python)));
+    }
+    out
+}
+
+fn conda_environment(conf: &Config) -> String {
+    let mut out = format!("name: {}\nchannels:\n  - conda-forge\ndependencies:\n", yaml_str(&conf.project.name));
+    match python_version() {
+        Some(python) => out.push_str(&format!("  - python={}\n", python)),
+        None => out.push_str("  - python\n"),
+    }
+    out.push_str("  - pip\n");
+    let reqs = conf.requirements();
+    if !reqs.is_empty() {
+        out.push_str("  - pip:\n");
+        for req in reqs.iter() {
+            out.push_str(&format!("    - {}\n", yaml_str(&req.to_string())));
+        }
+    }
+    out
+}
+
+fn dockerfile(conf: &Config) -> String {
+    // a stale lock may be missing dependencies, which `--no-deps` would leave out of the image
+    let lock = match LockFile::load_from_file(LOCK_FILE) {
+        Ok(lock) if lock.matches(&conf.lock_requirements()) => Some(lock),
+        Ok(_) => {
+            wprint("ppm.lock is out of date with project.toml, only the direct dependencies will be pinned, run 'ppm install' to update it".to_owned());
+            None
+        },
+        Err(_) => {
+            wprint("No ppm.lock found, only the direct dependencies will be pinned".to_owned());
+            None
+        },
+    };
+    let reqs = pinned_requirements(&conf.install_requirements(), lock.as_ref(), true);
+
+    let python = python_version().unwrap_or_else(|| "3".to_string());
+    let mut out = format!("FROM python:{}-slim\n\nWORKDIR /app\n\n", python);
+    if !reqs.is_empty() {
+        // the lock is complete, so pip doesn't need to resolve anything
+        let deps = reqs.values().map(|r| format!(" \\\n    '{}'", r.to_string().replace('\'', "\"")));
+        let no_deps = if lock.is_some() { " --no-deps" } else { "" };
+        out.push_str(&format!("RUN pip install --no-cache-dir{}{}\n\n", no_deps, deps.collect::<String>()));
+    }
+    out.push_str("COPY . .\n\n");
+
+    let entry = conf.entry();
+    let mut env: Vec<(&String, &String)> = entry.env.iter().collect();
+    env.sort();
+    for (key, value) in env {
+        out.push_str(&format!("ENV {}={}\n", key, serde_json::Value::String(value.clone())));
+    }
+    if let Some(cwd) = &entry.cwd {
+        out.push_str("ENV PYTHONPATH=/app\n");
+        out.push_str(&format!("WORKDIR /app/{}\n", cwd.trim_start_matches("./")));
+    }
+    if let Ok(target) = entry.target() {
+        let target = match target {
+            EntryTarget::Path(path) if entry.cwd.is_some() => EntryTarget::Path(format!("/app/{}", path.trim_start_matches("./"))),
+            target => target,
+        };
+        let mut cmd = vec!["python".to_string()];
+        cmd.extend(target.python_args());
+        cmd.extend(entry.args.iter().cloned());
+        out.push_str(&format!("CMD {}\n", serde_json::Value::from(cmd)));
+    }
+    out
+}
+
+
+#[derive(Args, Debug)]
+pub struct ExportProject {
+
+    /// Format to export to: pyproject, pipfile, conda or dockerfile
+    #[clap(short = 'f', long = "format")]
+    pub format: String,
+
+    /// Write to this file instead of printing
+    #[clap(short = 'o', long = "output")]
+    pub output: Option<String>,
+
+}
+
+impl ExportProject {
+    pub fn export(&self) {
+        if !Path::new("project.toml").exists() {
+            eprint("Could not find project.toml".to_owned());
+            return;
+        }
+
+        let conf = match Config::load_from_file("project.toml") {
+            Ok(conf) => conf,
+            Err(e) => {
+                eprint(e.to_string());
+                return;
+            }
+        };
+
+        let contents = match self.format.to_lowercase().as_str() {
+            "pyproject" | "pyproject.toml" => pyproject(&conf),
+            "pipfile" => pipfile(&conf),
+            "conda" | "environment.yml" => conda_environment(&conf),
+            "dockerfile" | "docker" => dockerfile(&conf),
+            other => {
+                eprint(format!("Unknown format '{}', use pyproject, pipfile, conda or dockerfile", other));
+                return;
+            }
+        };
+
+        match &self.output {
+            Some(path) => match write_atomic(path, &contents) {
+                Ok(_) => iprint(format!("Exported {}", path)),
+                Err(e) => eprint(format!("Could not write {}: {}", path, e)),
+            },
+            None => print!("{}", contents),
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::{fs, path::PathBuf, collections::HashMap};
+use crate::metadata::parse_headers;
+use crate::requirement::{normalize_name, Requirement};
+use crate::version::Version;
+use crate::wheel::{read_wheel_metadata, sha256_hex, WheelName};
+
+const PYPI_URL: &str = "https://pypi.org/pypi";
+
+/// A release of a project as listed on the index
+#[derive(Debug, Clone)]
+pub struct Release {
+    pub version: Version,
+    pub requires_python: Option<String>,
+    pub yanked: bool,
+}
+
+/// A downloadable file of a release, `url` is either a http(s) url or a local path
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub filename: String,
+    pub url: String,
+    pub sha256: Option<String>,
+}
+
+impl Artifact {
+    pub fn is_wheel(&self) -> bool {
+        self.filename.ends_with(".whl")
+    }
+
+    pub fn is_local(&self) -> bool {
+        !(self.url.starts_with("http://") || self.url.starts_with("https://"))
+    }
+}
+
+/// The dependency metadata of one release
+#[derive(Debug, Clone)]
+pub struct ReleaseMetadata {
+    pub requires_dist: Vec<Requirement>,
+    pub requires_python: Option<String>,
+    pub files: Vec<Artifact>,
+}
+
+/// A wheel found in the `PPM_FIND_LINKS` directory
+#[derive(Debug, Clone)]
+struct LocalWheel {
+    name: WheelName,
+    path: PathBuf,
+}
+
+/// Cached access to the PyPI JSON API
+pub struct PackageIndex {
+    base_url: String,
+    names: HashMap<String, String>,
+    releases: HashMap<String, Vec<Release>>,
+    metadata: HashMap<(String, String), ReleaseMetadata>,
+    local: Vec<LocalWheel>,
+}
+
+impl PackageIndex {
+    pub fn new() -> PackageIndex {
+        // PPM_INDEX_URL points ppm at a mirror serving the same JSON API
+        let base_url = std::env::var("PPM_INDEX_URL").unwrap_or_else(|_| PYPI_URL.to_string());
+        PackageIndex {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            names: HashMap::new(),
+            releases: HashMap::new(),
+            metadata: HashMap::new(),
+            local: find_local_wheels(),
+        }
+    }
+
+    fn local_wheels(&self, key: &str) -> Vec<&LocalWheel> {
+        self.local.iter().filter(|w| normalize_name(&w.name.name) == key).collect()
+    }
+
+    fn get_json(&self, url: &str) -> Result<serde_json::Value, String> {
+        let resp = reqwest::blocking::get(url).map_err(|e| e.to_string())?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err("not found on the index".to_string());
+        }
+        if !resp.status().is_success() {
+            return Err(format!("index returned {}", resp.status()));
+        }
+        resp.json().map_err(|e| e.to_string())
+    }
+
+    /// the project name as spelled on the index
+    pub fn display_name(&self, key: &str) -> String {
+        self.names.get(key).cloned().unwrap_or_else(|| key.to_string())
+    }
+
+    /// all releases of a project, newest first
+    pub fn releases(&mut self, name: &str) -> Result<Vec<Release>, String> {
+        let key = normalize_name(name);
+        if let Some(releases) = self.releases.get(&key) {
+            return Ok(releases.clone());
+        }
+
+        let mut releases: Vec<Release> = vec![];
+        let local: Vec<LocalWheel> = self.local_wheels(&key).into_iter().cloned().collect();
+        match self.get_json(&format!("{}/{}/json", self.base_url, key)) {
+            Ok(json) => {
+                if let Some(display) = json["info"]["name"].as_str() {
+                    self.names.insert(key.clone(), display.to_string());
+                }
+                if let Some(map) = json["releases"].as_object() {
+                    for (ver, files) in map.iter() {
+                        let files = match files.as_array() {
+                            Some(files) if !files.is_empty() => files,
+                            // releases without files can't be installed
+                            _ => continue,
+                        };
+                        let version = match Version::parse(ver) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+                        releases.push(Release {
+                            version,
+                            requires_python: files[0]["requires_python"].as_str().map(|s| s.to_string()),
+                            yanked: files.iter().all(|f| f["yanked"].as_bool().unwrap_or(false)),
+                        });
+                    }
+                }
+            },
+            // local wheels are enough when the index can't be reached
+            Err(_) if !local.is_empty() => {},
+            Err(e) => return Err(format!("Could not fetch '{}': {}", name, e)),
+        }
+
+        for wheel in local.iter() {
+            if let Some(version) = Version::parse(&wheel.name.version) {
+                if !releases.iter().any(|r| r.version == version) {
+                    releases.push(Release { version, requires_python: None, yanked: false });
+                }
+            }
+        }
+        if let Some(wheel) = local.first() {
+            self.names.entry(key.clone()).or_insert_with(|| wheel.name.name.clone());
+        }
+        releases.sort_by(|a, b| b.version.cmp(&a.version));
+        self.releases.insert(key, releases.clone());
+        Ok(releases)
+    }
+
+    /// the requirements of a single release
+    pub fn metadata(&mut self, name: &str, version: &str) -> Result<ReleaseMetadata, String> {
+        let cache_key = (normalize_name(name), version.to_string());
+        if let Some(meta) = self.metadata.get(&cache_key) {
+            return Ok(meta.clone());
+        }
+
+        let local: Vec<Artifact> = self.local_wheels(&cache_key.0)
+            .iter()
+            .filter(|w| Version::parse(&w.name.version).map(|v| Some(v) == Version::parse(version)).unwrap_or(false))
+            .map(|w| Artifact {
+                filename: w.name.filename.clone(),
+                url: w.path.to_string_lossy().to_string(),
+                sha256: fs::read(&w.path).ok().map(|data| sha256_hex(&data)),
+            })
+            .collect();
+
+        // metadata of a local wheel is read from the wheel itself
+        if let Some(wheel) = local.first() {
+            let contents = read_wheel_metadata(&PathBuf::from(&wheel.url))
+                .map_err(|e| format!("Could not read {}: {}", wheel.filename, e))?;
+            let headers = parse_headers(&contents);
+            let values = |name: &str| headers.iter().filter(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.clone()).collect::<Vec<String>>();
+            let meta = ReleaseMetadata {
+                requires_dist: values("Requires-Dist").iter().filter_map(|r| Requirement::parse(r)).collect(),
+                requires_python: values("Requires-Python").first().cloned(),
+                files: local,
+            };
+            self.metadata.insert(cache_key, meta.clone());
+            return Ok(meta);
+        }
+
+        let json = self.get_json(&format!("{}/{}/{}/json", self.base_url, cache_key.0, version))
+            .map_err(|e| format!("Could not fetch metadata of '{}' {}: {}", name, version, e))?;
+        let requires_dist = match json["info"]["requires_dist"].as_array() {
+            Some(reqs) => reqs.iter().filter_map(|r| r.as_str()).filter_map(Requirement::parse).collect(),
+            None => vec![],
+        };
+        let files = match json["urls"].as_array() {
+            Some(urls) => urls
+                .iter()
+                .filter_map(|f| Some(Artifact {
+                    filename: f["filename"].as_str()?.to_string(),
+                    url: f["url"].as_str()?.to_string(),
+                    sha256: f["digests"]["sha256"].as_str().map(|s| s.to_string()),
+                }))
+                .collect(),
+            None => vec![],
+        };
+        let meta = ReleaseMetadata {
+            requires_dist,
+            requires_python: json["info"]["requires_python"].as_str().filter(|s| !s.is_empty()).map(|s| s.to_string()),
+            files,
+        };
+        self.metadata.insert(cache_key, meta.clone());
+        Ok(meta)
+    }
+}
+
+#[cfg(test)]
+impl PackageIndex {
+    /// an index that only knows the given `(name, version, requires_dist)` releases
+    pub fn fake(packages: &[(&str, &str, &[&str])]) -> PackageIndex {
+        let mut index = PackageIndex {
+            base_url: String::new(),
+            names: HashMap::new(),
+            releases: HashMap::new(),
+            metadata: HashMap::new(),
+            local: vec![],
+        };
+        for (name, version, requires_dist) in packages.iter() {
+            let key = normalize_name(name);
+            index.names.insert(key.clone(), name.to_string());
+            let releases = index.releases.entry(key.clone()).or_default();
+            releases.push(Release { version: Version::parse(version).unwrap(), requires_python: None, yanked: false });
+            releases.sort_by(|a, b| b.version.cmp(&a.version));
+            index.metadata.insert((key, version.to_string()), ReleaseMetadata {
+                requires_dist: requires_dist.iter().filter_map(|r| Requirement::parse(r)).collect(),
+                requires_python: None,
+                files: vec![Artifact {
+                    filename: format!("{}-{}-py3-none-any.whl", name, version),
+                    url: String::new(),
+                    sha256: Some(sha256_hex(format!("{}-{}", name, version).as_bytes())),
+                }],
+            });
+        }
+        index
+    }
+}
+
+/// scan the `PPM_FIND_LINKS` directory for wheels
+fn find_local_wheels() -> Vec<LocalWheel> {
+    let dir = match std::env::var("PPM_FIND_LINKS") {
+        Ok(dir) if !dir.is_empty() => dir,
+        _ => return vec![],
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .flatten()
+        .filter_map(|e| {
+            let name = WheelName::parse(&e.file_name().to_string_lossy())?;
+            Some(LocalWheel { name, path: e.path() })
+        })
+        .collect()
+}
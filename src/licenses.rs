@@ -0,0 +1,331 @@
+use clap::Args;
+use colored::*;
+use std::path::Path;
+use crate::lockfile::{LockFile, LOCK_FILE};
+use crate::metadata::{installed_distributions, Distribution};
+use crate::resolver::Resolution;
+use crate::settings::{Config, LicensePolicy};
+use crate::utils::*;
+
+/// SPDX identifiers for the last part of `License ::` classifiers
+const CLASSIFIER_LICENSES: &[(&str, &str)] = &[
+    ("Apache Software License", "Apache-2.0"),
+    ("MIT License", "MIT"),
+    ("MIT No Attribution License (MIT-0)", "MIT-0"),
+    ("ISC License (ISCL)", "ISC"),
+    ("Python Software Foundation License", "PSF-2.0"),
+    ("The Unlicense (Unlicense)", "Unlicense"),
+    ("zlib/libpng License", "Zlib"),
+    ("Boost Software License 1.0 (BSL-1.0)", "BSL-1.0"),
+    ("Historical Permission Notice and Disclaimer (HPND)", "HPND"),
+    ("CC0 1.0 Universal (CC0 1.0) Public Domain Dedication", "CC0-1.0"),
+    ("Mozilla Public License 1.1 (MPL 1.1)", "MPL-1.1"),
+    ("Mozilla Public License 2.0 (MPL 2.0)", "MPL-2.0"),
+    ("Eclipse Public License 2.0 (EPL-2.0)", "EPL-2.0"),
+    ("European Union Public Licence 1.2 (EUPL 1.2)", "EUPL-1.2"),
+    ("GNU General Public License (GPL)", "GPL"),
+    ("GNU General Public License v2 (GPLv2)", "GPL-2.0-only"),
+    ("GNU General Public License v2 or later (GPLv2+)", "GPL-2.0-or-later"),
+    ("GNU General Public License v3 (GPLv3)", "GPL-3.0-only"),
+    ("GNU General Public License v3 or later (GPLv3+)", "GPL-3.0-or-later"),
+    ("GNU Library or Lesser General Public License (LGPL)", "LGPL"),
+    ("GNU Lesser General Public License v2 (LGPLv2)", "LGPL-2.0-only"),
+    ("GNU Lesser General Public License v2 or later (LGPLv2+)", "LGPL-2.0-or-later"),
+    ("GNU Lesser General Public License v3 (LGPLv3)", "LGPL-3.0-only"),
+    ("GNU Lesser General Public License v3 or later (LGPLv3+)", "LGPL-3.0-or-later"),
+    ("GNU Affero General Public License v3", "AGPL-3.0-only"),
+    ("GNU Affero General Public License v3 or later (AGPLv3+)", "AGPL-3.0-or-later"),
+];
+
+/// the SPDX identifier of a classifier, or the classifier itself if it has none
+fn classifier_license(classifier: &str) -> String {
+    CLASSIFIER_LICENSES
+        .iter()
+        .find(|(name, _)| *name == classifier)
+        .map(|(_, spdx)| spdx.to_string())
+        .unwrap_or_else(|| classifier.to_string())
+}
+
+/// The license a distribution declares in its metadata
+pub struct LicenseInfo {
+    pub name: String,
+    pub version: String,
+    /// PEP 639 `License-Expression`
+    pub expression: Option<String>,
+    /// the last part of `License ::` classifiers, as SPDX identifiers where known
+    pub classifiers: Vec<String>,
+    /// the free-form `License` field
+    pub license: Option<String>,
+}
+
+impl LicenseInfo {
+    pub fn from_distribution(dist: &Distribution) -> LicenseInfo {
+        let field = |name: &str| dist.get_all(name).into_iter().map(|v| v.trim().to_string()).find(|v| !v.is_empty() && v != "UNKNOWN");
+        LicenseInfo {
+            name: dist.name.clone(),
+            version: dist.version.clone(),
+            expression: field("License-Expression"),
+            classifiers: dist.get_all("Classifier")
+                .iter()
+                .filter(|c| c.starts_with("License ::"))
+                .filter_map(|c| c.rsplit("::").next())
+                .map(|c| c.trim())
+                .filter(|c| *c != "OSI Approved")
+                .map(classifier_license)
+                .collect(),
+            license: field("License"),
+        }
+    }
+
+    /// the most precise description available
+    pub fn summary(&self) -> String {
+        if let Some(expression) = &self.expression {
+            return expression.clone();
+        }
+        if !self.classifiers.is_empty() {
+            return self.classifiers.join(" / ");
+        }
+        match &self.license {
+            // some projects paste the whole license text here
+            Some(license) => {
+                let first = license.lines().next().unwrap_or("").trim();
+                if first.chars().count() > 50 || license.contains('\n') {
+                    format!("{}...", first.chars().take(47).collect::<String>())
+                } else {
+                    first.to_string()
+                }
+            },
+            None => "UNKNOWN".to_string(),
+        }
+    }
+
+    /// alternatives the user may pick from, each a list of licenses that all apply
+    fn alternatives(&self) -> Vec<Vec<String>> {
+        let expression = match (&self.expression, &self.license) {
+            (Some(expression), _) => expression.clone(),
+            (None, _) if !self.classifiers.is_empty() => {
+                return self.classifiers.iter().map(|c| vec![c.clone()]).collect();
+            },
+            (None, Some(license)) if !license.contains('\n') && license.len() <= 50 => license.clone(),
+            _ => return vec![],
+        };
+        expression
+            .split(" OR ")
+            .map(|alt| {
+                alt.split(" AND ")
+                    .map(|l| l.split(" WITH ").next().unwrap_or(l))
+                    .map(|l| l.trim_matches(|c: char| c == '(' || c == ')' || c.is_whitespace()).to_string())
+                    .filter(|l| !l.is_empty())
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+fn normalize_license(license: &str) -> String {
+    let mut license = license.trim().to_lowercase();
+    for suffix in [" license", " licence"] {
+        if let Some(stripped) = license.strip_suffix(suffix) {
+            license = stripped.to_string();
+        }
+    }
+    license.replace(" software", "").replace(' ', "-")
+}
+
+/// `MIT` matches `MIT License`, `Apache` matches `Apache-2.0`
+fn license_matches(license: &str, entry: &str) -> bool {
+    let license = normalize_license(license);
+    let entry = normalize_license(entry);
+    license == entry || license.starts_with(&format!("{}-", entry)) || entry.starts_with(&format!("{}-", license))
+}
+
+/// why a license breaks the policy, if it does
+pub fn policy_violation(policy: &LicensePolicy, info: &LicenseInfo) -> Option<String> {
+    let alternatives = info.alternatives();
+    if alternatives.is_empty() {
+        return if policy.allow.is_empty() { None } else { Some("license is unknown".to_string()) };
+    }
+
+    let denied = |l: &String| policy.deny.iter().any(|d| license_matches(l, d));
+    let allowed = |l: &String| policy.allow.is_empty() || policy.allow.iter().any(|a| license_matches(l, a));
+    if alternatives.iter().any(|alt| alt.iter().all(|l| !denied(l) && allowed(l))) {
+        return None;
+    }
+    match alternatives.iter().flatten().find(|l| denied(l)) {
+        Some(license) => Some(format!("{} is denied", license)),
+        None => Some(format!("{} is not in the allow list", info.summary())),
+    }
+}
+
+/// print every distribution that breaks the policy, true if there are none
+pub fn check_license_policy<'a>(policy: &LicensePolicy, dists: impl Iterator<Item = &'a Distribution>) -> bool {
+    let mut ok = true;
+    for dist in dists {
+        let info = LicenseInfo::from_distribution(dist);
+        if let Some(reason) = policy_violation(policy, &info) {
+            eprint(format!("License of {} {} not allowed: {}", info.name, info.version, reason));
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// check the packages of a resolution once they are installed, true if there's no policy
+pub fn check_resolution_licenses(conf: &Config, resolution: &Resolution) -> bool {
+    let policy = match conf.policy.licenses.as_ref() {
+        Some(policy) => policy,
+        None => return true,
+    };
+    match installed_distributions() {
+        Ok(dists) => check_license_policy(policy, dists.values().filter(|d| resolution.packages.contains_key(&d.key()))),
+        Err(_) => false,
+    }
+}
+
+
+#[derive(Args, Debug)]
+pub struct ShowLicenses {
+
+    /// Print the licenses as JSON
+    #[clap(long = "json", takes_value = false)]
+    pub json: bool,
+
+}
+
+impl ShowLicenses {
+    pub fn show(&self) {
+        if !Path::new("project.toml").exists() {
+            eprint("Could not find project.toml".to_owned());
+            return;
+        }
+
+        let conf = match Config::load_from_file("project.toml") {
+            Ok(conf) => conf,
+            Err(e) => {
+                eprint(e.to_string());
+                return;
+            }
+        };
+
+        let dists = match installed_distributions() {
+            Ok(dists) => dists,
+            Err(_) => return,
+        };
+        // the dependencies are what's in the lock, without it everything in the venv
+        let mut dists: Vec<&Distribution> = match LockFile::load_from_file(LOCK_FILE) {
+            Ok(lock) => {
+                let locked = lock.packages_by_key();
+                dists.values().filter(|d| locked.contains_key(&d.key())).collect()
+            },
+            Err(_) => dists.values().collect(),
+        };
+        dists.sort_by_key(|d| d.key());
+
+        let infos: Vec<LicenseInfo> = dists.iter().map(|d| LicenseInfo::from_distribution(d)).collect();
+        let policy = conf.policy.licenses.as_ref();
+
+        if self.json {
+            let entries: Vec<serde_json::Value> = infos
+                .iter()
+                .map(|info| {
+                    let mut entry = serde_json::json!({
+                        "name": info.name,
+                        "version": info.version,
+                        "license": info.summary(),
+                        "license_expression": info.expression,
+                        "classifiers": info.classifiers,
+                    });
+                    if let Some(policy) = policy {
+                        entry["allowed"] = serde_json::Value::Bool(policy_violation(policy, info).is_none());
+                    }
+                    entry
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+            return;
+        }
+
+        let name_width = infos.iter().map(|i| i.name.len()).max().unwrap_or(0).max(4);
+        let version_width = infos.iter().map(|i| i.version.len()).max().unwrap_or(0).max(7);
+        println!("{:nw$}  {:vw$}  {}", "Name".green().bold(), "Version".green().bold(), "License".green().bold(), nw = name_width, vw = version_width);
+        for info in infos.iter() {
+            let license = match policy.and_then(|p| policy_violation(p, info)) {
+                Some(reason) => format!("{} ({})", info.summary(), reason).bright_red(),
+                None => info.summary().normal(),
+            };
+            println!("{:nw$}  {:vw$}  {}", info.name.bright_cyan(), info.version.bright_yellow(), license, nw = name_width, vw = version_width);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::parse_headers;
+
+    fn info(metadata: &str) -> LicenseInfo {
+        let dist = Distribution {
+            name: "pkg".to_string(),
+            version: "1.0".to_string(),
+            dist_info: std::path::PathBuf::new(),
+            headers: parse_headers(metadata),
+        };
+        LicenseInfo::from_distribution(&dist)
+    }
+
+    fn policy(allow: &[&str], deny: &[&str]) -> LicensePolicy {
+        LicensePolicy {
+            allow: allow.iter().map(|l| l.to_string()).collect(),
+            deny: deny.iter().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn matches_license_names_loosely() {
+        assert!(license_matches("MIT License", "MIT"));
+        assert!(license_matches("Apache-2.0", "Apache"));
+        assert!(license_matches("Apache Software License", "apache"));
+        assert!(license_matches("BSD-3-Clause", "BSD"));
+        assert!(license_matches("GPL-3.0-only", "GPL-3.0"));
+        assert!(!license_matches("LGPL-3.0-only", "GPL"));
+        assert!(!license_matches("MPL-2.0", "MIT"));
+    }
+
+    #[test]
+    fn maps_classifiers_to_spdx() {
+        let info = info("Name: pkg\nClassifier: License :: OSI Approved :: GNU General Public License v3 (GPLv3)\nClassifier: License :: OSI Approved :: Apache Software License\nClassifier: License :: Other/Proprietary License\nClassifier: Programming Language :: Python\n");
+        assert_eq!(info.classifiers, vec!["GPL-3.0-only", "Apache-2.0", "Other/Proprietary License"]);
+        assert_eq!(info.summary(), "GPL-3.0-only / Apache-2.0 / Other/Proprietary License");
+    }
+
+    #[test]
+    fn prefers_the_expression_and_shortens_license_texts() {
+        let expression = info("License-Expression: MIT OR Apache-2.0\nLicense: UNKNOWN\nClassifier: License :: OSI Approved :: BSD License\n");
+        assert_eq!(expression.summary(), "MIT OR Apache-2.0");
+        let text = format!("License: {}\n        second line\n", "x".repeat(60));
+        assert_eq!(info(&text).summary(), format!("{}...", "x".repeat(47)));
+        assert_eq!(info("License: UNKNOWN\n").summary(), "UNKNOWN");
+    }
+
+    #[test]
+    fn denies_gpl_classifiers() {
+        let gpl = info("Classifier: License :: OSI Approved :: GNU General Public License v3 (GPLv3)\n");
+        assert_eq!(policy_violation(&policy(&[], &["GPL-3.0"]), &gpl), Some("GPL-3.0-only is denied".to_string()));
+        assert_eq!(policy_violation(&policy(&[], &["GPL"]), &gpl), Some("GPL-3.0-only is denied".to_string()));
+        assert_eq!(policy_violation(&policy(&["MIT"], &[]), &gpl), Some("GPL-3.0-only is not in the allow list".to_string()));
+        let apache = info("Classifier: License :: OSI Approved :: Apache Software License\n");
+        assert_eq!(policy_violation(&policy(&["Apache-2.0"], &["GPL"]), &apache), None);
+    }
+
+    #[test]
+    fn any_allowed_alternative_is_enough() {
+        let dual = info("License-Expression: (GPL-2.0-only WITH Classpath-exception-2.0) OR MIT\n");
+        assert_eq!(policy_violation(&policy(&["MIT"], &["GPL"]), &dual), None);
+        let both = info("License-Expression: MIT AND GPL-2.0-only\n");
+        assert_eq!(policy_violation(&policy(&[], &["GPL"]), &both), Some("GPL-2.0-only is denied".to_string()));
+        let unknown = info("Name: pkg\n");
+        assert_eq!(policy_violation(&policy(&["MIT"], &[]), &unknown), Some("license is unknown".to_string()));
+        assert_eq!(policy_violation(&policy(&[], &["GPL"]), &unknown), None);
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, io::Error, collections::{HashMap, HashSet}};
+use crate::requirement::{normalize_name, Requirement};
+use crate::resolver::{Resolution, ResolvedPackage};
+use crate::version::Version;
+
+pub const LOCK_FILE: &str = "ppm.lock";
+
+/// A package pinned in the lockfile
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    /// `sha256:<hex>` for every artifact of this release
+    #[serde(default)]
+    pub hashes: Vec<String>,
+}
+
+/// The fully resolved dependency set, written next to project.toml
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct LockFile {
+    /// the top-level requirements the lock was resolved from
+    #[serde(default)]
+    pub requirements: Vec<String>,
+    #[serde(default)]
+    pub package: Vec<LockedPackage>,
+}
+
+impl LockFile {
+    pub fn from_resolution(roots: &[Requirement], resolution: &Resolution) -> LockFile {
+        let mut requirements: Vec<String> = roots.iter().map(|r| r.to_string()).collect();
+        requirements.sort();
+        let package = resolution.packages
+            .values()
+            .map(|p| LockedPackage {
+                name: p.name.clone(),
+                version: p.version.to_string(),
+                dependencies: p.dependencies.iter().map(|d| d.to_string()).collect(),
+                hashes: p.hashes.iter().map(|h| format!("sha256:{}", h)).collect(),
+            })
+            .collect();
+        LockFile { requirements, package }
+    }
+
+    /// true if the lock was resolved from exactly these top-level requirements
+    pub fn matches(&self, roots: &[Requirement]) -> bool {
+        let mut requirements: Vec<String> = roots.iter().map(|r| r.to_string()).collect();
+        requirements.sort();
+        self.requirements == requirements
+    }
+
+    pub fn packages_by_key(&self) -> HashMap<String, &LockedPackage> {
+        self.package.iter().map(|p| (normalize_name(&p.name), p)).collect()
+    }
+
+    /// the locked packages as if they had just been resolved
+    pub fn to_resolution(&self) -> Resolution {
+        let packages = self.package
+            .iter()
+            .filter_map(|p| {
+                let version = Version::parse(&p.version)?;
+                let dependencies = p.dependencies.iter().filter_map(|d| Requirement::parse(d)).collect();
+                let hashes = p.hashes.iter().filter_map(|h| h.strip_prefix("sha256:")).map(|h| h.to_lowercase()).collect();
+                Some((normalize_name(&p.name), ResolvedPackage { name: p.name.clone(), version, dependencies, artifacts: vec![], hashes }))
+            })
+            .collect();
+        Resolution { packages }
+    }
+
+    /// keys of the locked packages `roots` depend on, directly or not
+    pub fn reachable(&self, roots: &[Requirement]) -> HashSet<String> {
+        let by_key = self.packages_by_key();
+        let mut reachable = HashSet::new();
+        let mut stack: Vec<String> = roots.iter().map(|r| r.key()).collect();
+        while let Some(key) = stack.pop() {
+            if !reachable.insert(key.clone()) {
+                continue;
+            }
+            if let Some(pkg) = by_key.get(&key) {
+                stack.extend(pkg.dependencies.iter().filter_map(|d| Requirement::parse(d)).map(|r| r.key()));
+            }
+        }
+        reachable
+    }
+
+    /// drop packages that are no longer reachable from `roots`
+    pub fn prune(&mut self, roots: &[Requirement]) {
+        let reachable = self.reachable(roots);
+        self.package.retain(|p| reachable.contains(&normalize_name(&p.name)));
+
+        let mut requirements: Vec<String> = roots.iter().map(|r| r.to_string()).collect();
+        requirements.sort();
+        self.requirements = requirements;
+    }
+
+    pub fn load_from_file(path: &str) -> Result<LockFile, Error> {
+        let toml_string = fs::read_to_string(path)?;
+        let lock: LockFile = toml::from_str(&toml_string)?;
+        Ok(lock)
+    }
+}
@@ -2,6 +2,26 @@ mod project_managers;
 mod utils;
 mod settings;
 mod ppm_functions;
+mod requirement;
+mod metadata;
+mod dep_tree;
+mod version;
+mod markers;
+mod index;
+mod resolver;
+mod lockfile;
+mod transaction;
+mod wheel;
+mod cache;
+mod audit;
+mod licenses;
+mod export;
+mod scripts;
+mod process;
+mod watch;
+mod environment;
+mod shell;
+mod templates;
 
 use project_managers::Action;
 use clap::Parser;
@@ -16,12 +36,20 @@ const AUTHOR : &'static str = env!("CARGO_PKG_AUTHORS");
 #[clap(author=AUTHOR, version=VERSION, about=ABOUT, long_about = None)]
 struct Cli {
 
+    /// Profile from [profiles.<name>] to use, instead of PPM_PROFILE
+    #[clap(long = "profile", global = true)]
+    profile: Option<String>,
+
     #[clap(subcommand)]
     command: Action,
 }
 
 fn main() {
     let cli = Cli::parse();
+    // set for everything ppm runs too, so nested ppm calls use the same profile
+    if let Some(profile) = &cli.profile {
+        std::env::set_var("PPM_PROFILE", profile);
+    }
 
     match &cli.command {
         Action::New(project) => project.create_project(false),
@@ -29,10 +57,20 @@ fn main() {
         Action::Add(add_proj) => add_proj.add_package(),
         Action::Rm(rp) => rp.remove_package(),
         Action::Run(run) => run.run_script(),
+        Action::Exec(exec) => exec.exec(),
         Action::Install(installer) => installer.install_packages(),
         Action::Info => ppm_functions::show_project_info(),
-        Action::Gen => ppm_functions::gen_requirements(),
-        Action::Start => ppm_functions::start_project(),
+        Action::Gen(gen) => ppm_functions::gen_requirements(gen),
+        Action::Start(start) => ppm_functions::start_project(start),
         Action::Update => ppm_functions::update_packages(),
+        Action::Tree => dep_tree::show_dependency_tree(),
+        Action::Why(why) => why.explain(),
+        Action::Cache(cache) => cache.run(),
+        Action::Audit(audit) => audit.run(),
+        Action::Licenses(licenses) => licenses.show(),
+        Action::Check => ppm_functions::check_project(),
+        Action::Export(export) => export.export(),
+        Action::Env(env) => env.show(),
+        Action::Shell(shell) => shell.run(),
     }
 }
\ No newline at end of file
@@ -0,0 +1,320 @@
+use std::{collections::HashMap, process::Command};
+use crate::version::{Specifier, Version};
+
+/// Values of the PEP 508 marker variables for a python interpreter
+#[derive(Debug, Clone)]
+pub struct MarkerEnv {
+    pub values: HashMap<String, String>,
+}
+
+const ENV_SCRIPT: &str = "\
+import os, sys, platform
+impl = sys.implementation
+iver = '{0.major}.{0.minor}.{0.micro}'.format(impl.version)
+print(os.name)
+print(sys.platform)
+print(platform.system())
+print(platform.machine())
+print(platform.python_implementation())
+print(platform.release())
+print(platform.version())
+print('.'.join(platform.python_version_tuple()[:2]))
+print(platform.python_version())
+print(impl.name)
+print(iver)
+";
+
+const ENV_KEYS: [&str; 11] = [
+    "os_name",
+    "sys_platform",
+    "platform_system",
+    "platform_machine",
+    "platform_python_implementation",
+    "platform_release",
+    "platform_version",
+    "python_version",
+    "python_full_version",
+    "implementation_name",
+    "implementation_version",
+];
+
+impl MarkerEnv {
+    /// ask the given interpreter for its marker values
+    pub fn from_python(python: &str) -> Result<MarkerEnv, String> {
+        let output = Command::new(python)
+            .arg("-c")
+            .arg(ENV_SCRIPT)
+            .output()
+            .map_err(|e| format!("Could not run '{}': {}", python, e))?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).to_string());
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let values = ENV_KEYS
+            .iter()
+            .zip(stdout.lines())
+            .map(|(k, v)| (k.to_string(), v.trim().to_string()))
+            .collect();
+        Ok(MarkerEnv { values })
+    }
+
+    pub fn python_version(&self) -> Option<Version> {
+        Version::parse(self.values.get("python_full_version")?)
+    }
+
+    /// `major.minor` of the interpreter, e.g. `3.11`
+    pub fn python_short_version(&self) -> Option<String> {
+        self.values.get("python_version").cloned()
+    }
+
+    /// evaluate a marker expression, `extra` is the extra being requested, if any
+    pub fn evaluate(&self, marker: &str, extra: Option<&str>) -> bool {
+        let tokens = tokenize(marker);
+        let mut parser = Parser { tokens, pos: 0, env: self, extra };
+        // an unparsable marker is treated as not applying
+        parser.parse_or().unwrap_or(false)
+    }
+}
+
+/// true if the marker compares the `extra` variable, so it only applies when an extra is requested
+pub fn uses_extra(marker: &str) -> bool {
+    tokenize(marker).contains(&Token::Ident("extra".to_string()))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Op(String),
+    LParen,
+    RParen,
+}
+
+fn tokenize(marker: &str) -> Vec<Token> {
+    let chars: Vec<char> = marker.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let end = chars[i + 1..].iter().position(|&q| q == c).map(|p| p + i + 1).unwrap_or(chars.len());
+            tokens.push(Token::Str(chars[i + 1..end].iter().collect()));
+            i = end + 1;
+        } else if "<>=!~".contains(c) {
+            let start = i;
+            while i < chars.len() && "<>=!~".contains(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token::Op(chars[start..i].iter().collect()));
+        } else {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                i += 1;
+            }
+            if i == start {
+                // unknown character, skip it
+                i += 1;
+                continue;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        }
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    env: &'a MarkerEnv,
+    extra: Option<&'a str>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Option<bool> {
+        let mut value = self.parse_and()?;
+        while self.peek() == Some(&Token::Ident("or".to_string())) {
+            self.pos += 1;
+            let rhs = self.parse_and()?;
+            value = value || rhs;
+        }
+        Some(value)
+    }
+
+    fn parse_and(&mut self) -> Option<bool> {
+        let mut value = self.parse_expr()?;
+        while self.peek() == Some(&Token::Ident("and".to_string())) {
+            self.pos += 1;
+            let rhs = self.parse_expr()?;
+            value = value && rhs;
+        }
+        Some(value)
+    }
+
+    fn parse_expr(&mut self) -> Option<bool> {
+        if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            let value = self.parse_or()?;
+            if self.next()? != Token::RParen {
+                return None;
+            }
+            return Some(value);
+        }
+        let lhs = self.next()?;
+        let op = match self.next()? {
+            Token::Op(op) => op,
+            Token::Ident(word) if word == "in" => "in".to_string(),
+            Token::Ident(word) if word == "not" => {
+                match self.next()? {
+                    Token::Ident(word) if word == "in" => "not in".to_string(),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        let rhs = self.next()?;
+
+        let is_extra = lhs == Token::Ident("extra".to_string()) || rhs == Token::Ident("extra".to_string());
+        let lhs = self.resolve(lhs)?;
+        let rhs = self.resolve(rhs)?;
+        if is_extra {
+            // extras compare by normalized name
+            let norm = |s: &str| crate::requirement::normalize_name(s);
+            return Some(match op.as_str() {
+                "==" => norm(&lhs) == norm(&rhs),
+                "!=" => norm(&lhs) != norm(&rhs),
+                _ => false,
+            });
+        }
+        Some(compare(&lhs, &op, &rhs))
+    }
+
+    fn resolve(&self, token: Token) -> Option<String> {
+        match token {
+            Token::Str(s) => Some(s),
+            Token::Ident(name) if name == "extra" => Some(self.extra.unwrap_or("").to_string()),
+            Token::Ident(name) => {
+                // python_implementation is a legacy alias
+                let key = if name == "python_implementation" { "platform_python_implementation".to_string() } else { name };
+                Some(self.env.values.get(&key).cloned().unwrap_or_default())
+            },
+            _ => None,
+        }
+    }
+}
+
+fn compare(lhs: &str, op: &str, rhs: &str) -> bool {
+    match op {
+        "in" => return rhs.contains(lhs),
+        "not in" => return !rhs.contains(lhs),
+        _ => {}
+    }
+    // use version comparison when both sides look like versions
+    if let (Some(lv), Some(spec)) = (Version::parse(lhs), Specifier::parse(&format!("{}{}", op, rhs))) {
+        // `== '3.11.*'` is a prefix match
+        let rhs = if op == "==" || op == "!=" { rhs.strip_suffix(".*").unwrap_or(rhs) } else { rhs };
+        if Version::parse(rhs).is_some() {
+            return spec.contains(&lv);
+        }
+    }
+    match op {
+        "==" | "===" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        "<=" => lhs <= rhs,
+        ">" => lhs > rhs,
+        ">=" => lhs >= rhs,
+        _ => false,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env() -> MarkerEnv {
+        let values = [
+            ("os_name", "posix"),
+            ("sys_platform", "linux"),
+            ("platform_system", "Linux"),
+            ("platform_machine", "x86_64"),
+            ("platform_python_implementation", "CPython"),
+            ("python_version", "3.11"),
+            ("python_full_version", "3.11.4"),
+            ("implementation_name", "cpython"),
+        ];
+        MarkerEnv { values: values.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect() }
+    }
+
+    #[test]
+    fn compares_versions_as_versions() {
+        let env = env();
+        assert!(env.evaluate("python_version >= '3.8'", None));
+        assert!(env.evaluate("python_version > '3.9'", None));
+        assert!(!env.evaluate("python_version < '3.10'", None));
+        assert!(env.evaluate("python_full_version == '3.11.*'", None));
+        assert!(env.evaluate("'3.12' > python_version", None));
+    }
+
+    #[test]
+    fn compares_strings() {
+        let env = env();
+        assert!(env.evaluate("sys_platform == 'linux'", None));
+        assert!(env.evaluate("sys_platform != \"win32\"", None));
+        assert!(env.evaluate("'arm' not in platform_machine", None));
+        assert!(env.evaluate("'x86' in platform_machine", None));
+        assert!(env.evaluate("python_implementation == 'CPython'", None));
+    }
+
+    #[test]
+    fn combines_with_and_or_and_parentheses() {
+        let env = env();
+        assert!(env.evaluate("sys_platform == 'win32' or os_name == 'posix'", None));
+        assert!(!env.evaluate("sys_platform == 'win32' and os_name == 'posix'", None));
+        assert!(env.evaluate("(sys_platform == 'win32' or sys_platform == 'linux') and python_version >= '3'", None));
+        // and binds tighter than or
+        assert!(env.evaluate("os_name == 'posix' or sys_platform == 'win32' and python_version < '3'", None));
+    }
+
+    #[test]
+    fn extras_compare_normalized() {
+        let env = env();
+        assert!(!env.evaluate("extra == 'socks'", None));
+        assert!(env.evaluate("extra == 'socks'", Some("socks")));
+        assert!(env.evaluate("extra == 'Test_Utils'", Some("test-utils")));
+        assert!(env.evaluate("python_version >= '3' and extra == \"cli\"", Some("cli")));
+    }
+
+    #[test]
+    fn unparsable_markers_do_not_apply() {
+        let env = env();
+        assert!(!env.evaluate("python_version >=", None));
+        assert!(!env.evaluate("(os_name == 'posix'", None));
+    }
+
+    #[test]
+    fn finds_extra_comparisons() {
+        assert!(uses_extra("extra == 'socks'"));
+        assert!(uses_extra("python_version < '3.8' and extra == \"test\""));
+        assert!(!uses_extra("platform_machine == 'x86_64'"));
+        assert!(!uses_extra("sys_platform == 'extra'"));
+    }
+}
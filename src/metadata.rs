@@ -0,0 +1,130 @@
+use std::{fs, path::{Path, PathBuf}, collections::HashMap};
+use crate::requirement::{normalize_name, Requirement};
+use crate::utils::*;
+
+/// An installed distribution, read from its `*.dist-info/METADATA` file
+#[derive(Debug, Clone)]
+pub struct Distribution {
+    pub name: String,
+    pub version: String,
+    pub dist_info: PathBuf,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Distribution {
+    pub fn from_dist_info(dist_info: &Path) -> Option<Distribution> {
+        let contents = fs::read_to_string(dist_info.join("METADATA")).ok()?;
+        let headers = parse_headers(&contents);
+        let name = header_value(&headers, "Name")?;
+        let version = header_value(&headers, "Version")?;
+        Some(Distribution {
+            name,
+            version,
+            dist_info: dist_info.to_path_buf(),
+            headers,
+        })
+    }
+
+    pub fn key(&self) -> String {
+        normalize_name(&self.name)
+    }
+
+    /// all values of a header, in file order
+    pub fn get_all(&self, header: &str) -> Vec<String> {
+        self.headers
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(header))
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
+    /// every file installed by this distribution, from its RECORD
+    pub fn record_files(&self) -> Vec<PathBuf> {
+        let site_packages = match self.dist_info.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => return vec![],
+        };
+        let record = match fs::read_to_string(self.dist_info.join("RECORD")) {
+            Ok(record) => record,
+            Err(_) => return vec![],
+        };
+        record
+            .lines()
+            .filter_map(|line| {
+                // path,hash,size where the path may be quoted
+                let path = match line.strip_prefix('"') {
+                    Some(rest) => rest.split('"').next()?,
+                    None => line.split(',').next()?,
+                };
+                if path.is_empty() { None } else { Some(site_packages.join(path)) }
+            })
+            .collect()
+    }
+
+    pub fn requires_dist(&self) -> Vec<Requirement> {
+        self.get_all("Requires-Dist")
+            .iter()
+            .filter_map(|r| Requirement::parse(r))
+            .collect()
+    }
+}
+
+fn header_value(headers: &[(String, String)], header: &str) -> Option<String> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(header))
+        .map(|(_, v)| v.clone())
+}
+
+/// parse the RFC 822 style header block of a METADATA file
+pub fn parse_headers(contents: &str) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in contents.lines() {
+        if line.is_empty() {
+            // body starts after the first blank line
+            break;
+        }
+        if line.starts_with(' ') || line.starts_with('\t') {
+            // continuation of the previous header
+            if let Some((_, value)) = headers.last_mut() {
+                value.push('\n');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            headers.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    headers
+}
+
+/// read every distribution installed in the project venv, keyed by normalized name
+pub fn installed_distributions() -> Result<HashMap<String, Distribution>, ()> {
+    let site_packages = match venv_site_packages() {
+        Some(path) => path,
+        None => {
+            eprint("Could not find site-packages in venv".to_owned());
+            return Err(());
+        }
+    };
+    let entries = match fs::read_dir(&site_packages) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprint(e.to_string());
+            return Err(());
+        }
+    };
+
+    let mut dists = HashMap::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() || !entry.file_name().to_string_lossy().ends_with(".dist-info") {
+            continue;
+        }
+        if let Some(dist) = Distribution::from_dist_info(&path) {
+            dists.insert(dist.key(), dist);
+        }
+    }
+    Ok(dists)
+}
@@ -1,6 +1,18 @@
 pub(crate) use std::path::Path;
 use std::process::{Command, Stdio};
-use crate::settings::Config;
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap};
+use crate::settings::{package_key, split_marker, Config, EntryTarget};
+use crate::licenses::{check_license_policy, check_resolution_licenses};
+use crate::lockfile::{LockFile, LOCK_FILE};
+use crate::markers;
+use crate::metadata::installed_distributions;
+use crate::project_managers::{GenRequirements, StartProject};
+use crate::environment::{apply_project_env, is_secret, mask, project_env};
+use crate::process::run_foreground;
+use crate::watch::watch;
+use crate::requirement::{normalize_name, Requirement};
+use crate::resolver::{resolve_requirements, Resolution};
+use crate::transaction::apply_resolution;
 use crate::utils::*;
 use colored::*;
 
@@ -19,8 +31,8 @@ pub fn show_project_info() {
     };
     println!("");
 
-    // get pyhon versiopn from ./venv/Scripts/python
-    match Command::new("./venv/Scripts/python.exe")
+    // get pyhon versiopn from the venv
+    match Command::new(venv_executable("python"))
         .arg("--version")
         .stdout(Stdio::piped())
         .output()
@@ -38,12 +50,37 @@ pub fn show_project_info() {
     println!("{}: {}", "Project".green().bold(), conf.project.name.bright_cyan().bold());
     println!("{}: {}", "Version".green().bold(), conf.project.version.bright_red().bold());
     println!("{}: {}", "Description".green().bold(), conf.project.description.bright_white().bold());
+    if let Some(profile) = &conf.profile {
+        println!("{}: {}", "Profile".green().bold(), profile.bright_magenta().bold());
+    }
+    let entry = conf.entry();
+    if let Some(target) = &entry.target {
+        let mut command = vec![target.clone()];
+        command.extend(entry.args.iter().cloned());
+        println!("{}: {}", "Entry".green().bold(), shell_join(&command).bright_white().bold());
+    }
+    if let Some(profile) = conf.active_profile().filter(|p| !p.groups.is_empty()) {
+        println!("{}: {}", "Groups".green().bold(), profile.groups.join(", ").bright_white().bold());
+    }
     
     println!("");
-    let count = conf.scripts.len();
+    let scripts = conf.effective_scripts();
+    let count = scripts.len();
     println!("-- {} {} --", count.to_string().green().bold(),  if count == 1 { "Script".to_owned() } else { "Scripts".to_owned() });
-    for (name, cmd) in conf.scripts.iter() {
-        println!("{}: {}", name.bright_yellow().bold(), cmd.green().bold());
+    for (name, script) in scripts.iter() {
+        println!("{}: {}", name.bright_yellow().bold(), script.describe().green().bold());
+    }
+
+    if let Ok(env) = project_env() {
+        if !env.vars.is_empty() {
+            println!();
+            let count = env.vars.len();
+            println!("-- {} {} --", count.to_string().green().bold(), if count == 1 { "Env Var".to_owned() } else { "Env Vars".to_owned() });
+            for (key, (value, _)) in env.vars.iter() {
+                let value = if is_secret(key) { mask(value) } else { value.clone() };
+                println!("{}={}", key.bright_yellow().bold(), value.green().bold());
+            }
+        }
     }
 
     println!("");
@@ -59,7 +96,100 @@ pub fn show_project_info() {
     
 }
 
-pub fn gen_requirements() {
+// a marker as alternatives of conjunctions, an empty conjunction meaning always
+type Condition = BTreeSet<BTreeSet<String>>;
+
+/// add `conj` to the alternatives for `key` unless one it implies is already there
+fn add_condition(needed: &mut HashMap<String, Condition>, stack: &mut Vec<String>, key: String, conj: BTreeSet<String>) {
+    let condition = needed.entry(key.clone()).or_default();
+    if condition.iter().any(|c| c.is_subset(&conj)) {
+        return;
+    }
+    condition.retain(|c| !conj.is_subset(c));
+    condition.insert(conj);
+    stack.push(key);
+}
+
+/// the marker under which each locked package reachable from `roots` is needed,
+/// `None` if it always is, a dependency's marker is combined with its parent's
+fn transitive_markers(roots: &[Requirement], lock: &LockFile) -> HashMap<String, Option<String>> {
+    let locked = lock.packages_by_key();
+    // extras were already decided when the lock was resolved
+    let atom = |req: &Requirement| req.marker.clone().filter(|m| !markers::uses_extra(m));
+    let mut needed: HashMap<String, Condition> = HashMap::new();
+    let mut stack = vec![];
+
+    for root in roots.iter() {
+        add_condition(&mut needed, &mut stack, root.key(), atom(root).into_iter().collect());
+    }
+    while let Some(key) = stack.pop() {
+        let pkg = match locked.get(&key) {
+            Some(pkg) => pkg,
+            None => continue,
+        };
+        let condition = needed[&key].clone();
+        for dep in pkg.dependencies.iter().filter_map(|d| Requirement::parse(d)) {
+            for conj in condition.iter() {
+                let mut conj = conj.clone();
+                conj.extend(atom(&dep));
+                add_condition(&mut needed, &mut stack, dep.key(), conj);
+            }
+        }
+    }
+
+    let conjunction = |conj: &BTreeSet<String>| -> String {
+        if conj.len() == 1 {
+            return conj.iter().next().unwrap().clone();
+        }
+        conj.iter().map(|m| format!("({})", m)).collect::<Vec<String>>().join(" and ")
+    };
+    needed
+        .into_iter()
+        .map(|(key, condition)| {
+            let marker = if condition.iter().any(|c| c.is_empty()) {
+                None
+            } else if condition.len() == 1 {
+                condition.iter().next().map(conjunction)
+            } else {
+                Some(condition.iter().map(|c| format!("({})", conjunction(c))).collect::<Vec<String>>().join(" or "))
+            };
+            (key, marker)
+        })
+        .collect()
+}
+
+/// `roots` pinned to their locked versions, keyed by package, plus every locked package they depend on if `transitive`
+pub fn pinned_requirements(roots: &[Requirement], lock: Option<&LockFile>, transitive: bool) -> BTreeMap<String, Requirement> {
+    let locked = lock.map(|l| l.packages_by_key()).unwrap_or_default();
+    let mut entries: BTreeMap<String, Requirement> = BTreeMap::new();
+    for root in roots.iter() {
+        let mut req = root.clone();
+        // unpinned packages get the version they were locked at
+        if req.specifier.is_empty() {
+            if let Some(pkg) = locked.get(&req.key()) {
+                req.specifier = format!("=={}", pkg.version);
+            }
+        }
+        entries.entry(req.key()).or_insert(req);
+    }
+    if let (true, Some(lock)) = (transitive, lock) {
+        let mut markers = transitive_markers(roots, lock);
+        for pkg in lock.package.iter() {
+            let key = normalize_name(&pkg.name);
+            // only what these roots pull in, not every group in the lock
+            let marker = match markers.remove(&key) {
+                Some(marker) => marker,
+                None => continue,
+            };
+            if let Entry::Vacant(entry) = entries.entry(key) {
+                entry.insert(Requirement { name: pkg.name.clone(), extras: vec![], specifier: format!("=={}", pkg.version), marker });
+            }
+        }
+    }
+    entries
+}
+
+pub fn gen_requirements(gen: &GenRequirements) {
     if !Path::new("project.toml").exists() {
         eprint("Could not find project.toml".to_owned());
         return;
@@ -73,18 +203,98 @@ pub fn gen_requirements() {
         }
     };
 
+    let constraints = match gen.format.as_str() {
+        "requirements" => false,
+        "constraints" => true,
+        other => {
+            eprint(format!("Unknown format '{}', use requirements or constraints", other));
+            return;
+        }
+    };
+
+    let mut roots = conf.install_requirements();
+    let mut groups = gen.groups.clone();
+    if gen.dev && !groups.iter().any(|g| g == "dev") {
+        groups.push("dev".to_string());
+    }
+    for group in groups.iter() {
+        match conf.group_requirements(group) {
+            Some(reqs) => roots.extend(reqs),
+            None => {
+                eprint(format!("No [groups.{}] table in project.toml", group));
+                return;
+            }
+        }
+    }
+
+    // pip's hash-checking mode needs every package pinned, not just the direct ones
+    let transitive = gen.include_transitive || gen.with_hashes;
+    let lock = match LockFile::load_from_file(LOCK_FILE) {
+        // a lock from other requirements would leave dependencies out or unhashed
+        Ok(lock) if transitive && !lock.matches(&conf.lock_requirements()) => {
+            eprint("ppm.lock is out of date with project.toml, run 'ppm install'".to_owned());
+            return;
+        },
+        Ok(lock) => Some(lock),
+        Err(_) if transitive => {
+            eprint("Could not read ppm.lock, run 'ppm install' to create it".to_owned());
+            return;
+        },
+        Err(_) => None,
+    };
+    let locked = lock.as_ref().map(|l| l.packages_by_key()).unwrap_or_default();
+
+    let mut entries = pinned_requirements(&roots, lock.as_ref(), transitive);
+
     let mut reqs = String::new();
-    for (name, version) in conf.packages.iter() {
-        reqs.push_str(&format!("{}=={}\n", name, version));
+    let mut missing = vec![];
+    for (key, req) in entries.iter_mut() {
+        if constraints {
+            // constraints files can't carry extras and only make sense pinned
+            req.extras.clear();
+            if req.specifier.is_empty() {
+                continue;
+            }
+        }
+        reqs.push_str(&req.to_string());
+        if gen.with_hashes {
+            let hashes = locked
+                .get(key)
+                .filter(|p| req.specifier == format!("=={}", p.version))
+                .map(|p| p.hashes.clone())
+                .unwrap_or_default();
+            if hashes.is_empty() {
+                missing.push(req.name.clone());
+            }
+            for hash in hashes.iter() {
+                reqs.push_str(&format!(" \\\n    --hash={}", hash));
+            }
+        }
+        reqs.push('\n');
+    }
+    if !missing.is_empty() {
+        wprint(format!("No hashes in ppm.lock for: {}, pip will refuse to install them", missing.join(", ")));
+    }
+
+    if gen.check {
+        match std::fs::read_to_string(&gen.output) {
+            Ok(existing) if existing == reqs => iprint(format!("{} is up to date", gen.output)),
+            _ => {
+                eprint(format!("{} is out of date, run 'ppm gen' to update it", gen.output));
+                std::process::exit(1);
+            }
+        }
+        return;
     }
-    match std::fs::write("requirements.txt", reqs) {
-        Ok(_) => iprint("Generated requirements.txt ".to_owned()),
-        Err(e) => eprint(format!("Could not write requirements.txt: {}", e)),
+
+    match write_atomic(&gen.output, &reqs) {
+        Ok(_) => iprint(format!("Generated {}", gen.output)),
+        Err(e) => eprint(format!("Could not write {}: {}", gen.output, e)),
     }
     
 }
 
-pub fn start_project() {
+pub fn start_project(start: &StartProject) {
     if !Path::new("project.toml").exists() {
         eprint("Could not find project.toml".to_owned());
         return;
@@ -98,22 +308,57 @@ pub fn start_project() {
         }
     };
 
-    let venv = Command::new("./venv/Scripts/python.exe")
-                .arg(conf.project.main_script)
-                .stdin(Stdio::piped())
-                .spawn();
-    
-    match venv {
-        Ok(mut o) => {
-            let _ = o.wait();
-            let _ = o.kill();
+    if let Err(e) = project_env() {
+        eprint(e.clone());
+        std::process::exit(1);
+    }
+
+    if start.watch {
+        let mut argv = vec!["start".to_string(), "--".to_string()];
+        argv.extend(start.args.iter().cloned());
+        std::process::exit(watch(&argv, &conf.watch));
+    }
+
+    let entry = conf.entry();
+    let target = match entry.target() {
+        Ok(target) => target,
+        Err(e) => {
+            eprint(e);
+            std::process::exit(1);
+        }
+    };
+    // paths stay relative to the project when the entry has its own working dir
+    let root = std::env::current_dir().unwrap_or_default();
+    let python = if check_venv_dir_exists() { root.join(venv_python()).to_string_lossy().to_string() } else { venv_python() };
+    let target = match target {
+        EntryTarget::Path(path) => EntryTarget::Path(root.join(path.trim_start_matches("./")).to_string_lossy().to_string()),
+        target => target,
+    };
+
+    let mut cmd = Command::new(python);
+    cmd.args(target.python_args()).args(&entry.args).args(&start.args);
+    apply_project_env(&mut cmd);
+    activate_venv(&mut cmd);
+    if let Some(cwd) = &entry.cwd {
+        cmd.current_dir(cwd);
+        // modules of the project must still be importable
+        let mut paths = vec![root.clone()];
+        if let Some(path) = std::env::var_os("PYTHONPATH") {
+            paths.extend(std::env::split_paths(&path));
+        }
+        if let Ok(path) = std::env::join_paths(paths) {
+            cmd.env("PYTHONPATH", path);
         }
+    }
+    cmd.envs(&entry.env);
+    match run_foreground(&mut cmd) {
+        Ok(code) => std::process::exit(code),
         Err(e) => {
             eprint("Failed to start main file".to_owned());
             eprint(e.to_string());
+            std::process::exit(1);
         }
     }
-
 }
 
 
@@ -140,7 +385,7 @@ pub fn update_packages() {
     if !check_venv_dir_exists() {
         wprint("Could not find venv directory".to_owned());
         if ask_if_create_venv() {
-            if setup_venv("./venv".to_owned()).is_err() {
+            if setup_venv(venv_dir()).is_err() {
                 eprint("Failed to setup venv".to_owned());
                 return;
             }
@@ -150,53 +395,177 @@ pub fn update_packages() {
         }
     }
 
-    let mut cmd_args: Vec<(String, String)> = vec![];
-    for (name, _) in conf.packages.iter() {
-        let latest_ver = get_pkg_version(&name.to_owned());
-        if latest_ver.is_err() {
-            eprint(format!("Could not find latest version of {}", name));
-            continue;
-        }
-        let latest_ver = latest_ver.unwrap();
-        cmd_args.push((name.to_owned(), latest_ver));
+    // drop the pins so every package can move to its newest compatible version
+    let unpin = |reqs: Vec<Requirement>| -> Vec<Requirement> {
+        reqs.into_iter()
+            .map(|mut r| {
+                r.specifier = String::new();
+                r
+            })
+            .collect()
+    };
+    let resolution = match resolve_requirements(&unpin(conf.lock_requirements())) {
+        Ok(resolution) => resolution,
+        Err(_) => return,
+    };
+    let install = resolution.subset(&unpin(conf.install_requirements()));
+    let tx = match apply_resolution(&install, false) {
+        Ok(tx) => tx,
+        Err(_) => return,
+    };
+    if !check_resolution_licenses(&conf, &install) {
+        tx.rollback();
+        return;
     }
 
-
-    /*
-        looping to check if each package was successfully installed
-        and add it to the ini file if it was
-    */
-    let mut conf_pkgs: Vec<(String, String)> = vec![];
-
-    for (name, ver) in cmd_args {
-        let mut cmd = Command::new("./venv/Scripts/pip.exe");
-        cmd.arg("install");
-        cmd.arg(format!("{}=={}", name, ver));
-    
-        let venv = cmd.spawn();
+    let updated = repin(&mut conf, &resolution);
+    let lock = LockFile::from_resolution(&conf.lock_requirements(), &resolution);
+    if tx.commit(&conf, Some(&lock)).is_ok() {
+        for msg in updated {
+            iprint(msg);
+        }
+    }
     
-        match venv {
-            Ok(mut o) => {
-                let _ = o.wait();
-                conf_pkgs.push((name.to_owned(), ver.to_owned()));
-                iprint(format!("Updated {}", name));
-            }
-            Err(e) => {
-                eprint(format!("Failed to update '{}'", name));
-                eprint(e.to_string());
+}
+/// pin [packages] and every `[groups.<name>]` table to the resolved versions,
+/// so the lock's requirements match what it was resolved to
+fn repin(conf: &mut Config, resolution: &Resolution) -> Vec<String> {
+    let mut updated = vec![];
+    let mut groups: Vec<(&String, &mut HashMap<String, String>)> = conf.groups.iter_mut().collect();
+    groups.sort_by(|a, b| a.0.cmp(b.0));
+    let tables = std::iter::once((None, &mut conf.packages)).chain(groups.into_iter().map(|(name, table)| (Some(name), table)));
+    for (group, table) in tables {
+        let mut names: Vec<&String> = table.keys().collect();
+        names.sort();
+        let mut changes = vec![];
+        for name in names {
+            if let Some(pkg) = resolution.packages.get(&package_key(name)) {
+                let (version, marker) = split_marker(&table[name]);
+                if version != pkg.version.to_string() {
+                    let label = group.map(|g| format!(" [groups.{}]", g)).unwrap_or_default();
+                    updated.push(format!("Updated {} {} -> {}{}", name, version, pkg.version, label));
+                    let value = match marker {
+                        Some(marker) => format!("{}; {}", pkg.version, marker),
+                        None => pkg.version.to_string(),
+                    };
+                    changes.push((name.clone(), value));
+                }
             }
         }
+        table.extend(changes);
     }
+    updated
+}
 
-    for (name, ver) in conf_pkgs {
-        conf.packages.insert(name.to_string(), ver);
+/// exits non-zero if ppm.lock, the venv or the license policy need attention
+pub fn check_project() {
+    if !Path::new("project.toml").exists() {
+        eprint("Could not find project.toml".to_owned());
+        std::process::exit(1);
     }
 
-    match conf.write_to_file("project.toml") {
-        Ok(_) => {}
+    let conf = match Config::load_from_file("project.toml") {
+        Ok(conf) => conf,
         Err(e) => {
             eprint(e.to_string());
+            std::process::exit(1);
+        }
+    };
+
+    let mut ok = true;
+    let lock = match LockFile::load_from_file(LOCK_FILE) {
+        Ok(lock) => {
+            if !lock.matches(&conf.lock_requirements()) {
+                eprint("ppm.lock is out of date with project.toml, run 'ppm install'".to_owned());
+                ok = false;
+            }
+            Some(lock)
+        },
+        Err(_) => {
+            wprint("No ppm.lock found, run 'ppm install' to create it".to_owned());
+            None
+        },
+    };
+
+    if check_venv_dir_exists() {
+        let dists = match installed_distributions() {
+            Ok(dists) => dists,
+            Err(_) => std::process::exit(1),
+        };
+        if let Some(lock) = lock.as_ref() {
+            // groups outside the active profile are locked but not installed
+            let needed = lock.reachable(&conf.install_requirements());
+            for pkg in lock.package.iter().filter(|p| needed.contains(&normalize_name(&p.name))) {
+                match dists.get(&normalize_name(&pkg.name)) {
+                    Some(dist) if dist.version == pkg.version => {},
+                    Some(dist) => {
+                        eprint(format!("{} {} is installed but ppm.lock has {}", pkg.name, dist.version, pkg.version));
+                        ok = false;
+                    },
+                    None => {
+                        eprint(format!("{} {} is locked but not installed", pkg.name, pkg.version));
+                        ok = false;
+                    },
+                }
+            }
+        }
+        if let Some(policy) = conf.policy.licenses.as_ref() {
+            let locked = lock.as_ref().map(|l| l.packages_by_key());
+            let deps = dists.values().filter(|d| locked.as_ref().map(|l| l.contains_key(&d.key())).unwrap_or(true));
+            ok &= check_license_policy(policy, deps);
         }
+    } else if conf.policy.licenses.is_some() {
+        wprint("Virtual Environment Not Found, skipping the license check".to_owned());
+    }
+
+    if !ok {
+        std::process::exit(1);
+    }
+    iprint("All checks passed".to_owned());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::ResolvedPackage;
+    use crate::version::Version;
+
+    fn resolution(pins: &[(&str, &str)]) -> Resolution {
+        let packages = pins
+            .iter()
+            .map(|(name, version)| {
+                let package = ResolvedPackage { name: name.to_string(), version: Version::parse(version).unwrap(), dependencies: vec![], artifacts: vec![], hashes: vec![] };
+                (normalize_name(name), package)
+            })
+            .collect();
+        Resolution { packages }
+    }
+
+    #[test]
+    fn update_repins_groups_along_with_packages() {
+        let mut conf: Config = toml::from_str(concat!(
+            "[project]\nname = \"p\"\nversion = \"0.1\"\ndescription = \"\"\n\n",
+            "[packages]\nrequests = \"2.0\"\n\n[scripts]\n\n",
+            "[groups.dev]\npytest = \"7.0\"\nBlack = \"23.1; python_version >= '3.8'\"\nrequests = \"2.0\"\n",
+        )).unwrap();
+        let resolution = resolution(&[("requests", "2.31.0"), ("pytest", "8.1"), ("black", "24.2"), ("urllib3", "2.2")]);
+
+        let updated = repin(&mut conf, &resolution);
+        assert_eq!(updated, vec![
+            "Updated requests 2.0 -> 2.31.0",
+            "Updated Black 23.1 -> 24.2 [groups.dev]",
+            "Updated pytest 7.0 -> 8.1 [groups.dev]",
+            "Updated requests 2.0 -> 2.31.0 [groups.dev]",
+        ]);
+        assert_eq!(conf.packages["requests"], "2.31.0");
+        assert_eq!(conf.groups["dev"]["Black"], "24.2; python_version >= '3.8'");
+        assert_eq!(conf.groups["dev"]["pytest"], "8.1");
+
+        // the lock written from the repinned project is not stale
+        let lock = LockFile::from_resolution(&conf.lock_requirements(), &resolution);
+        assert!(lock.matches(&conf.lock_requirements()));
+        assert_eq!(lock.requirements, vec!["Black==24.2; python_version >= '3.8'", "pytest==8.1", "requests==2.31.0"]);
+        assert!(repin(&mut conf, &resolution).is_empty());
     }
-    
 }
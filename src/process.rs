@@ -0,0 +1,138 @@
+use std::{io, process::{Child, Command, ExitStatus, Stdio}, sync::atomic::{AtomicBool, AtomicI32, Ordering}, thread, time::{Duration, Instant}};
+
+/// how long a process group gets to exit after being asked to
+const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// the last SIGINT/SIGTERM received, 0 for none
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// pids of the children signals are forwarded to, 0 for a free slot
+static CHILDREN: [AtomicI32; 64] = [const { AtomicI32::new(0) }; 64];
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    SIGNAL.store(signal, Ordering::SeqCst);
+    // Ctrl+C in the terminal already reaches children in our process group, the kernel
+    // sends those, only pass on what another process sent to ppm
+    if info.is_null() || unsafe { (*info).si_pid() } == 0 {
+        return;
+    }
+    for child in CHILDREN.iter() {
+        let pid = child.load(Ordering::SeqCst);
+        if pid != 0 {
+            unsafe { libc::kill(pid, signal) };
+        }
+    }
+}
+
+/// remember SIGINT/SIGTERM (Ctrl+C on Windows) instead of exiting and pass them on to
+/// the children being waited on, see `received_signal`
+pub fn catch_signals() {
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    #[cfg(unix)]
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+        libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut());
+    }
+    // the console sends Ctrl+C to every process attached to it, children included
+    #[cfg(windows)]
+    let _ = ctrlc::set_handler(|| SIGNAL.store(2, Ordering::SeqCst));
+}
+
+/// the signal caught by `catch_signals`, if one came in
+pub fn received_signal() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// the exit code of a process, 128+signal when a signal killed it
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// wait for a child, forwarding the signals ppm gets to it, and return its exit code
+pub fn wait_forwarding(child: &mut Child) -> i32 {
+    catch_signals();
+    let pid = child.id() as i32;
+    let slot = CHILDREN.iter().find(|s| s.compare_exchange(0, pid, Ordering::SeqCst, Ordering::SeqCst).is_ok());
+    let code = child.wait().map(exit_code).unwrap_or(1);
+    if let Some(slot) = slot {
+        slot.store(0, Ordering::SeqCst);
+    }
+    code
+}
+
+/// run a command in the foreground with ppm's stdin, stdout and stderr, returning its exit code
+pub fn run_foreground(cmd: &mut Command) -> io::Result<i32> {
+    let mut child = cmd.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit()).spawn()?;
+    Ok(wait_forwarding(&mut child))
+}
+
+/// spawn a command as the leader of a new process group, so it can be stopped
+/// along with everything it starts
+pub fn spawn_group(cmd: &mut Command) -> io::Result<Child> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+    cmd.spawn()
+}
+
+/// wait up to `timeout` for a child to exit, true if it did
+fn wait_timeout(child: &mut Child, timeout: Duration) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if let Ok(Some(_)) = child.try_wait() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+/// stop a child started by `spawn_group` and every process in its group:
+/// SIGTERM first, SIGKILL for whatever is left after the grace period
+#[cfg(unix)]
+pub fn terminate_group(child: &mut Child) {
+    let group = -(child.id() as libc::pid_t);
+    unsafe { libc::kill(group, libc::SIGTERM) };
+    wait_timeout(child, GRACE_PERIOD);
+    // the leader may be gone while some of its children still hang around
+    unsafe { libc::kill(group, libc::SIGKILL) };
+    let _ = child.wait();
+}
+
+/// stop a child started by `spawn_group` and the whole tree of processes below it
+#[cfg(windows)]
+pub fn terminate_group(child: &mut Child) {
+    let _ = Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &child.id().to_string()])
+        .output();
+    if !wait_timeout(child, GRACE_PERIOD) {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+}
@@ -1,18 +1,24 @@
 use clap::{Subcommand, Args};
 use colored::Colorize;
 
-use std::{fs, process::{Command}, io::{Write}, path::Path, time::Instant, collections::HashMap};
+use std::{fs, process::{Command}, io::{IsTerminal, Write}, path::{Path, PathBuf}, time::Instant, collections::HashMap};
 use crate::utils::*;
 use crate::settings::*;
-
-
-const STARTER_SOURCE_PY: &'static str = "\r
-def main():
-    print('Hello From PPM!')
-
-if __name__ == '__main__':
-    main()
-";
+use crate::audit::AuditPackages;
+use crate::cache::CacheCommand;
+use crate::dep_tree::WhyPackage;
+use crate::environment::{apply_project_env, project_env, ShowEnv};
+use crate::export::ExportProject;
+use crate::licenses::{check_resolution_licenses, ShowLicenses};
+use crate::lockfile::*;
+use crate::requirement::Requirement;
+use crate::resolver::{resolve_requirements, Resolution};
+use crate::process::run_foreground;
+use crate::scripts::{run_line, ScriptRunner};
+use crate::shell::OpenShell;
+use crate::templates::{base_context, default_author, Template};
+use crate::transaction::*;
+use crate::watch::watch;
 
 
 #[derive(Subcommand, Debug)]
@@ -29,23 +35,83 @@ pub enum Action {
     /// Remove packages from project
     Rm(RemovePackage),
 
-    /// Run a script defined in project.toml
+    /// Run a script defined in project.toml, arguments after the name are passed to it
+    #[clap(trailing_var_arg = true)]
     Run(RunScript),
 
+    /// Run any command in the project environment, looked up in the venv first
+    #[clap(trailing_var_arg = true)]
+    Exec(ExecCommand),
+
     /// Install packages from project.toml or provided requirements.txt
     Install(Installer),
 
-    /// Run main script defined in project.toml
-    Start,
+    /// Run the project's entry point, arguments after `--` are passed to it
+    #[clap(trailing_var_arg = true)]
+    Start(StartProject),
 
-    /// Generate requirements.txt file
-    Gen,
+    /// Generate a requirements.txt or constraints file
+    Gen(GenRequirements),
 
     /// Show the project.toml file
     Info,
 
     /// Update all packages 
     Update,
+
+    /// Show the dependency tree of the project
+    Tree,
+
+    /// Show why a package is installed
+    Why(WhyPackage),
+
+    /// Manage the download cache shared by all projects
+    Cache(CacheCommand),
+
+    /// Check installed packages against known vulnerabilities
+    Audit(AuditPackages),
+
+    /// Show the license of every dependency
+    Licenses(ShowLicenses),
+
+    /// Check the lockfile, venv and license policy are in order
+    Check,
+
+    /// Export the project as a pyproject.toml, Pipfile, conda environment.yml or Dockerfile
+    Export(ExportProject),
+
+    /// Show the environment variables the project sets, with secrets masked
+    Env(ShowEnv),
+
+    /// Start a shell with the venv activated and the project environment set
+    Shell(OpenShell),
+}
+
+/// What `ppm init` found in the directory and was told to keep, instead of creating its own
+#[derive(Debug, Default)]
+struct ExistingProject {
+    main_script: Option<String>,
+    /// `venv` or `.venv`
+    venv: Option<String>,
+    requirements: bool,
+    git: bool,
+}
+
+impl ExistingProject {
+    fn detect() -> ExistingProject {
+        ExistingProject {
+            main_script: ["main.py", "app.py", "__main__.py"]
+                .iter()
+                .find(|p| Path::new(p).is_file())
+                .map(|p| format!("./{}", p)),
+            venv: ["venv", ".venv"]
+                .iter()
+                .find(|d| Path::new(d).join("pyvenv.cfg").is_file())
+                .map(|d| d.to_string()),
+            requirements: Path::new("requirements.txt").is_file(),
+            git: Path::new(".git").exists(),
+        }
+    }
 }
 
 pub struct ProjectCreator {
@@ -69,8 +135,75 @@ impl ProjectCreator {
         }
     }
 
-    fn create_git(&self) -> Result<(), ()> {
-        if self.project.git {
+    /// ask which of the files found in the directory `ppm init` should use
+    fn adopt_existing(&self) -> ExistingProject {
+        let mut existing = ExistingProject::default();
+        if !self.is_init {
+            return existing;
+        }
+        let found = ExistingProject::detect();
+        if let Some(main_script) = found.main_script {
+            if confirm(&format!("Found {}, use it as main_script?", main_script)) {
+                existing.main_script = Some(main_script);
+            }
+        }
+        match found.venv {
+            _ if self.project.no_venv => {},
+            // where ppm would create it anyway
+            Some(venv) if venv == "venv" => {
+                iprint("Using the existing virtual environment in venv".to_string());
+                existing.venv = Some(venv);
+            },
+            Some(venv) if confirm(&format!("Found a virtual environment in {}, use it instead of creating venv?", venv)) => {
+                existing.venv = Some(venv);
+            },
+            _ => {},
+        }
+        if found.requirements {
+            existing.requirements = confirm("Found requirements.txt, import its packages into project.toml?");
+        }
+        if found.git {
+            existing.git = confirm("Found a git repository, add the build directory and venv to its .gitignore?");
+        }
+        existing
+    }
+
+    /// add lines missing from .gitignore, keeping what is there
+    fn update_gitignore(&self, entries: &[String]) -> Result<(), ()> {
+        let path = self.get_path_with(".gitignore");
+        let current = fs::read_to_string(&path).unwrap_or_default();
+        let missing: Vec<&String> = entries.iter().filter(|e| !current.lines().any(|l| l.trim() == e.as_str())).collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let mut git_ignore = match fs::OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprint(format!("Could not open {}: {}", path, e));
+                return Err(());
+            }
+        };
+        let mut text = String::new();
+        if !current.is_empty() && !current.ends_with('\n') {
+            text.push('\n');
+        }
+        for entry in missing {
+            text.push_str(entry);
+            text.push('\n');
+        }
+        if let Err(e) = git_ignore.write_all(text.as_bytes()) {
+            eprint(format!("Could not write {}: {}", path, e));
+            return Err(());
+        }
+        Ok(())
+    }
+
+    fn create_git(&self, existing: &ExistingProject) -> Result<(), ()> {
+        let found = self.is_init && Path::new(".git").exists();
+        if !existing.git && (!self.project.git || found) {
+            return Ok(());
+        }
+        if !found {
             let path = if self.is_init { ".".to_string() } else { format!("{}/", self.project.name) };
             let git_repo = Command::new("git")
                 .arg("init")
@@ -80,98 +213,188 @@ impl ProjectCreator {
                 eprint(git_repo.unwrap_err().to_string());
                 return Err(());
             }
-            // add build to gitignore
-            let git_ignore = fs::File::create(self.get_path_with(".gitignore"));
-            if git_ignore.is_err() {
-                eprint(git_ignore.unwrap_err().to_string());
-                return Err(());
+        }
+        // add build and the venv to gitignore
+        let mut entries = vec!["/build".to_string()];
+        if !self.project.no_venv {
+            entries.push(format!("/{}", existing.venv.as_deref().unwrap_or("venv")));
+        }
+        self.update_gitignore(&entries)
+    }
+    
+    fn create_boilerplate_files(&self, template: &Template) -> Result<(), ()> {
+        for file in template.files.iter() {
+            let path = PathBuf::from(self.get_path_with(&file.path));
+            if self.is_init && path.exists() {
+                wprint(format!("Keeping the existing {}", file.path));
+                continue;
             }
-            let mut git_ignore = git_ignore.unwrap();
-            match git_ignore.write_all(b"/build\n") {
-                Ok(_) => Ok(()),
-                Err(e) => {
+            if let Some(parent) = path.parent() {
+                if let Err(e) = fs::create_dir_all(parent) {
                     eprint(e.to_string());
-                    git_ignore.flush().unwrap();
                     return Err(());
                 }
             }
-        } else {
-            Ok(())
+            if let Err(e) = fs::write(&path, &file.contents) {
+                eprint(format!("Could not write {}: {}", path.display(), e));
+                return Err(());
+            }
         }
-        
+        Ok(())
     }
-    
-    fn create_boilerplate_files(&self) -> Result<(), ()> {
-        let proj_dest = self.get_path_with("src");
-        let main_file = fs::File::create(format!("{}/main.py", proj_dest));
-        if main_file.is_err() {
-            eprint(main_file.unwrap_err().to_string());
-            return Err(());
+
+    /// add the packages the template needs, from inside the new project
+    fn add_template_packages(&self, template: &Template) {
+        if template.packages.is_empty() {
+            return;
         }
-        // write started source
-        let mut main_file = main_file.unwrap();
-        match main_file.write_all(STARTER_SOURCE_PY.as_bytes()) {
-            Ok(_) => (),
-            Err(e) => {
-                eprint(e.to_string());
-                // close
-                main_file.flush().unwrap();
-                return Err(());
+        let hint = format!("run 'ppm add {}' in the project to install them", template.packages.join(" "));
+        if self.project.no_venv {
+            wprint(format!("The template uses {}, {}", template.packages.join(", "), hint));
+            return;
+        }
+        AddPackage { pkg_names: template.packages.clone() }.add_package();
+        let added = Config::load_from_file("project.toml")
+            .map(|conf| template.packages.iter().all(|p| conf.find_package(p).is_some()))
+            .unwrap_or(false);
+        if !added {
+            wprint(format!("Could not install {}, {}", template.packages.join(", "), hint));
+        }
+    }
+
+    /// install the packages of requirements.txt and record them in project.toml
+    fn import_requirements(&self) {
+        let hint = "run 'ppm install -r requirements.txt' to import them";
+        if self.project.no_venv {
+            wprint(format!("Virtual environment is disabled, {}", hint));
+            return;
+        }
+        Installer { requirements: "requirements.txt".to_string(), require_hashes: false }.install_packages();
+        let imported = Config::load_from_file("project.toml").map(|conf| !conf.packages.is_empty()).unwrap_or(false);
+        if !imported {
+            wprint(format!("Could not import requirements.txt, {}", hint));
+        }
+    }
+
+    /// run the template's post-generate commands, stopping at the first that fails
+    fn run_post_generate(&self, template: &Template) {
+        if template.post_generate.is_empty() {
+            return;
+        }
+        if self.project.no_post_generate {
+            wprint(format!("Skipping the template's commands: {}", template.post_generate.join("; ")));
+            return;
+        }
+        if template.remote {
+            iprint("The template runs these commands in the new project:".to_string());
+            for line in template.post_generate.iter() {
+                println!("  {}", line);
+            }
+            if !std::io::stdin().is_terminal() || !confirm("Run them?") {
+                wprint("Skipped the template's commands, run them yourself if you trust them".to_string());
+                return;
+            }
+        }
+        for line in template.post_generate.iter() {
+            iprint(format!("Running {}", line));
+            let code = run_line(line);
+            if code != 0 {
+                wprint(format!("'{}' exited with code {}, the project is created but may be incomplete", line, code));
+                return;
             }
         }
-        // close files
-        main_file.flush().unwrap();
-        Ok(())
     }
 
-    fn save_config(&self) -> Result<(), ()> {
+    fn save_config(&self, template: &Template, existing: &ExistingProject) -> Result<(), ()> {
         let mut conf = Config::new(
             Project::new(
                 self.project.name.clone(),
                 self.project.version.clone(),
                 self.project.description.clone(),
-                if self.is_init { "./main.py".to_string() } else { "./src/main.py".to_string() }  
+                template.main_script.clone(),
             ),
             HashMap::new(),
             HashMap::new(),
         );
-        conf.scripts.insert("upgrade-pip".to_string(), "python -m pip install --upgrade pip".to_string());
+        conf.scripts.insert("upgrade-pip".to_string(), Script::Simple(ScriptCommand::Line("python -m pip install --upgrade pip".to_string())));
+        for (name, cmd) in template.scripts.iter() {
+            conf.scripts.insert(name.clone(), Script::Simple(ScriptCommand::Line(cmd.clone())));
+        }
+        conf.env.extend(template.env.iter().cloned());
+        conf.project.venv = existing.venv.as_ref().filter(|venv| *venv != "venv").map(|venv| format!("./{}", venv));
 
         match conf.write_to_file(self.get_path_with("project.toml").as_str()){
             Ok(_) => Ok(()),
             Err(e) => {
                 eprint(e.to_string());
-                return Err(());
+                Err(())
             }
         }
     }
 
     pub fn create_project(&self) {
         let start = Instant::now();
-        let proj_dest = self.get_path_with("src");
         if project_exists(&self.project.name,  self.is_init) {
             eprint(format!("Project With Name '{}' Already Exists", &self.project.name));
             return;
         }
-        let dir_create = fs::create_dir_all(&proj_dest);
-        if dir_create.is_err() {
-            eprint(dir_create.unwrap_err().to_string());
+        let mut template = match Template::load(&self.project.template) {
+            Ok(template) => template,
+            Err(e) => {
+                eprint(e);
+                return;
+            }
+        };
+        let mut given = HashMap::new();
+        for var in self.project.vars.iter() {
+            match var.split_once('=') {
+                Some((key, value)) => given.insert(key.trim().to_string(), value.to_string()),
+                None => {
+                    eprint(format!("Invalid --var '{}', expected key=value", var));
+                    return;
+                }
+            };
+        }
+        let author = self.project.author.clone().unwrap_or_else(default_author);
+        let mut context = base_context(&self.project.name, &self.project.version, &self.project.description, &author);
+        template.resolve_variables(&mut context, &given);
+        if let Err(e) = template.render(&context) {
+            eprint(e);
             return;
-        } 
+        }
+
+        let existing = self.adopt_existing();
+        if let Some(venv) = &existing.venv {
+            set_venv_dir(&format!("./{}", venv));
+        }
+        if let Some(main_script) = &existing.main_script {
+            // the starter script would only sit next to the real one
+            if self.project.template == "app" {
+                template.files.clear();
+            }
+            template.main_script = main_script.clone();
+        }
+
+        if !self.is_init {
+            if let Err(e) = fs::create_dir_all(&self.project.name) {
+                eprint(e.to_string());
+                return;
+            }
+        }
         
-        // create main.py file
-        if self.create_boilerplate_files().is_err() {
+        // create the template's files
+        if self.create_boilerplate_files(&template).is_err() {
             return;
         }
 
         // setup git
-        if self.create_git().is_err() {
+        if self.create_git(&existing).is_err() {
             return;
         }
 
-        // venv
+        // venv, unless there is one already
         if !self.project.no_venv {
-            if setup_venv(self.get_path_with("venv")).is_err() {
+            if existing.venv.is_none() && setup_venv(self.get_path_with("venv")).is_err() {
                 eprint("Failed to setup venv".to_owned());
                 return;
             }
@@ -180,9 +403,19 @@ impl ProjectCreator {
         }
 
         // save
-        if self.save_config().is_err() {
+        if self.save_config(&template, &existing).is_err() {
            return;
         }
+        // the packages and post-generate commands work from inside the project
+        if !self.is_init && std::env::set_current_dir(&self.project.name).is_err() {
+            wprint(format!("Could not enter {}, skipping the rest of the template setup", self.project.name));
+        } else {
+            if existing.requirements {
+                self.import_requirements();
+            }
+            self.add_template_packages(&template);
+            self.run_post_generate(&template);
+        }
 
         let elapsed = start.elapsed();
         iprint(format!("{} in {}s", "Completed".green(), elapsed.as_secs()));
@@ -216,6 +449,22 @@ pub struct ProjectConf {
     #[clap(short = 'e', long = "no-venv", takes_value = false)]
     no_venv: bool,
 
+    /// Start from a template: app, lib, cli, flask, a directory or a git url
+    #[clap(short = 't', long = "template", default_value = "app")]
+    template: String,
+
+    /// Set Project Author, git's user.name by default
+    #[clap(short = 'a', long = "author")]
+    author: Option<String>,
+
+    /// Set a template variable, key=value
+    #[clap(long = "var", multiple_occurrences = true)]
+    vars: Vec<String>,
+
+    /// Don't run the template's post-generate commands
+    #[clap(long = "no-post-generate", takes_value = false)]
+    no_post_generate: bool,
+
 }
 
 
@@ -252,30 +501,64 @@ impl AddPackage {
             }
         };
 
+        if !check_venv_dir_exists() {
+            eprint("Virtual Environment Not Found".to_owned());
+            return;
+        }
+
+        let mut new_reqs = vec![];
         for pkg_name in self.pkg_names.iter() {
-            let (vname, mut ver) = parse_version(pkg_name.clone());
-
-            if install_package(pkg_name.clone()) {
-                if ver.len() == 0 {
-                    // if no version, set to latest
-                    let v = get_pkg_version(&pkg_name);
-                    if v.is_err() {
-                        continue;
-                    }
-                    ver = v.unwrap();
-                } 
-                conf.packages.insert(vname.clone(), ver.clone());
-                match conf.write_to_file("project.toml") {
-                    Ok(_) => {
-                        iprint(format!("Package '{}' added successfully", &vname));
-                    },
-                    Err(e) => {
-                        eprint(e.to_string());
-                        continue;
-                    }
+            match Requirement::parse(pkg_name) {
+                Some(req) => new_reqs.push(req),
+                None => {
+                    eprint(format!("Invalid package '{}'", pkg_name));
+                    return;
+                }
+            }
+        }
+
+        // existing pins stay, packages being added replace their old entry
+        let keep = |reqs: Vec<Requirement>| -> Vec<Requirement> {
+            let mut reqs: Vec<Requirement> = reqs
+                .into_iter()
+                .filter(|r| !new_reqs.iter().any(|n| n.key() == r.key()))
+                .collect();
+            reqs.extend(new_reqs.iter().cloned());
+            reqs
+        };
+        let roots = keep(conf.lock_requirements());
+
+        let resolution = match resolve_requirements(&roots) {
+            Ok(resolution) => resolution,
+            Err(_) => return,
+        };
+        let install = resolution.subset(&keep(conf.install_requirements()));
+        let tx = match apply_resolution(&install, false) {
+            Ok(tx) => tx,
+            Err(_) => return,
+        };
+
+        if !check_resolution_licenses(&conf, &install) {
+            tx.rollback();
+            return;
+        }
+
+        for req in new_reqs.iter() {
+            // a marker that doesn't apply here leaves the package unresolved, keep the version asked for
+            let version = match resolution.packages.get(&req.key()) {
+                Some(pkg) => pkg.version.to_string(),
+                None => req.specifier.strip_prefix("==").filter(|v| !v.contains(',')).unwrap_or("").to_string(),
+            };
+            conf.set_package(req, &version);
+        }
+        let lock = LockFile::from_resolution(&conf.lock_requirements(), &resolution);
+        if tx.commit(&conf, Some(&lock)).is_ok() {
+            for req in new_reqs.iter() {
+                if resolution.packages.contains_key(&req.key()) {
+                    iprint(format!("Package '{}' added successfully", &req.name));
+                } else {
+                    wprint(format!("Package '{}' added, but not installed: its marker doesn't match this environment", &req.name));
                 }
-            } else {
-                eprint(format!("Package '{}' failed to install", &vname));
             }
         }
     }
@@ -291,30 +574,30 @@ pub struct RemovePackage {
 
 impl RemovePackage {
     
-    fn uninstall_package(&self, pkg: String) -> bool {
+    fn uninstall_packages(&self, pkgs: &[String]) -> bool {
         if !check_venv_dir_exists() {
             eprint("Virtual Environment Not Found".to_owned());
             return false;
         }
-        iprint(format!("Uninstalling {}", pkg));
+        iprint(format!("Uninstalling {}", pkgs.join(", ")));
         // print any out put to stdout
-        let venv = Command::new("./venv/Scripts/pip.exe")
+        let mut pip = Command::new(venv_executable("pip"));
+        apply_project_env(&mut pip);
+        let venv = pip
             .arg("uninstall")
             .arg("-y")
-            .arg(pkg)
-            .spawn()
-            .unwrap();
+            .args(pkgs)
+            .spawn();
+        let venv = match venv {
+            Ok(venv) => venv,
+            Err(e) => {
+                eprint(e.to_string());
+                return false;
+            }
+        };
         
         match venv.wait_with_output() {
-            Ok(out) => {
-                if !out.status.success() {
-                    println!("{}", String::from_utf8_lossy(&out.stderr));
-                    return false;
-                } else {
-                    println!("{}", String::from_utf8_lossy(&out.stdout));
-                    return true;
-                }
-            },
+            Ok(out) => out.status.success(),
             Err(e) => {
                 eprint(e.to_string());
                 return false;
@@ -334,28 +617,44 @@ impl RemovePackage {
                 return;
             }
         };
+
+        // check everything up front so nothing is removed on a typo
         for pkg_name in self.pkg_names.iter() {
-            if !conf.packages.contains_key(pkg_name) {
+            if conf.find_package(pkg_name).is_none() {
                 eprint(format!("Package '{}' does not exist", pkg_name));
-                continue;
+                return;
+            }
+        }
+
+        let changes: Vec<(String, Option<String>)> = self.pkg_names.iter().map(|p| (package_key(p), None)).collect();
+        let tx = match Transaction::begin(&changes) {
+            Ok(tx) => tx,
+            Err(_) => return,
+        };
+        if !self.uninstall_packages(&self.pkg_names) {
+            eprint(format!("Failed to remove '{}'", self.pkg_names.join(", ")));
+            tx.rollback();
+            return;
+        }
+
+        for pkg_name in self.pkg_names.iter() {
+            if let Some(entry) = conf.find_package(pkg_name) {
+                conf.packages.remove(&entry);
+            }
+        }
+        // drop packages only the removed ones depended on from the lock
+        let lock = match LockFile::load_from_file(LOCK_FILE) {
+            Ok(mut lock) => {
+                lock.prune(&conf.lock_requirements());
+                Some(lock)
+            },
+            Err(_) => None,
+        };
+        if tx.commit(&conf, lock.as_ref()).is_ok() {
+            for pkg_name in self.pkg_names.iter() {
+                iprint(format!("Package '{}' removed successfully", pkg_name));
             }
-            if self.uninstall_package(pkg_name.to_string()) {
-                conf.packages.remove(pkg_name);
-                match conf.write_to_file("project.toml") {
-                    Ok(_) => {
-                        iprint(format!("Package '{}' removed successfully", pkg_name));      
-                    },
-                    Err(e) => {
-                        eprint(e.to_string());
-                        continue;
-                    }
-                }
-            } else {
-                eprint(format!("Failed to remove '{}'", pkg_name));
-            }  
         }
-        
-        
     }
 }
 
@@ -363,9 +662,21 @@ impl RemovePackage {
 #[derive(Args, Debug)]
 pub struct RunScript {
 
+    /// Run the script and the scripts given as arguments at the same time
+    #[clap(short = 'p', long = "parallel", takes_value = false)]
+    pub parallel: bool,
+
+    /// Run the script again whenever a project file changes
+    #[clap(short = 'w', long = "watch", takes_value = false)]
+    pub watch: bool,
+
     /// Script Name
     pub script_name: String,
 
+    /// Arguments for the script, appended or put in place of {args}
+    #[clap(allow_hyphen_values = true, multiple_values = true)]
+    pub args: Vec<String>,
+
 }
 
 impl RunScript {
@@ -382,41 +693,108 @@ impl RunScript {
             }
         };
 
-        if !conf.scripts.contains_key(self.script_name.as_str()) {
-            eprint(format!("Script with name '{}' does not exist", self.script_name));
-            return;
+        if let Err(e) = project_env() {
+            eprint(e.clone());
+            std::process::exit(1);
+        }
+
+        if self.watch {
+            let mut argv = vec!["run".to_string()];
+            if self.parallel {
+                argv.push("--parallel".to_string());
+            }
+            argv.push(self.script_name.clone());
+            argv.extend(self.args.iter().cloned());
+            std::process::exit(watch(&argv, &conf.watch));
         }
-        let cmd_str = conf.scripts.get(self.script_name.as_str()).unwrap();
-
-        // temporary, later will add support for other os properly
-        // currently missing alot of features
-        let mut cmd;
-        if cfg!(target_os = "windows") {
-            cmd = Command::new("cmd");
-            cmd.arg("/C");
-        } else if cfg!(target_os = "linux") {
-            cmd = Command::new("bash");
-            cmd.arg("-c");
+
+        let runner = ScriptRunner::new(&conf);
+        let code = if self.parallel {
+            let mut names = vec![self.script_name.clone()];
+            names.extend(self.args.iter().cloned());
+            runner.run_parallel(&names)
         } else {
-            eprint("Unsupported OS".to_owned());
+            runner.run(&self.script_name, &self.args)
+        };
+        if code != 0 {
+            std::process::exit(code);
+        }
+    }
+}
+
+
+#[derive(Args, Debug)]
+pub struct ExecCommand {
+
+    /// Program to run
+    pub command: String,
+
+    /// Arguments for the program, passed as they are
+    #[clap(allow_hyphen_values = true, multiple_values = true)]
+    pub args: Vec<String>,
+
+}
+
+impl ExecCommand {
+    pub fn exec(&self) {
+        if !Path::new("project.toml").exists() {
+            eprint("Could not find project.toml".to_owned());
             return;
         }
-        cmd.env("PATH", "./venv/Scripts");
-        cmd.arg(cmd_str); 
+        // loaded for the profile check, the command itself only needs the environment
+        if let Err(e) = Config::load_from_file("project.toml") {
+            eprint(e.to_string());
+            std::process::exit(1);
+        }
+        if let Err(e) = project_env() {
+            eprint(e.clone());
+            std::process::exit(1);
+        }
 
-        match cmd.spawn() {
-            Ok(mut o) => {
-                let _ = o.wait();
-            },
+        let prepare = |program: &Path| {
+            let mut cmd = Command::new(program);
+            apply_project_env(&mut cmd);
+            activate_venv(&mut cmd);
+            cmd
+        };
+        // the venv bin dir comes first on the PATH the command gets
+        let lookup = prepare(Path::new(&self.command));
+        let path = lookup.get_envs().find(|(key, _)| *key == "PATH").and_then(|(_, value)| value.map(|v| v.to_os_string()));
+        let program = match path.and_then(|path| find_executable(&self.command, &path)) {
+            Some(program) => program,
+            None => {
+                eprint(format!("Command not found: {}", self.command));
+                std::process::exit(127);
+            }
+        };
+
+        let mut cmd = prepare(&program);
+        cmd.args(&self.args);
+        match run_foreground(&mut cmd) {
+            Ok(code) => std::process::exit(code),
             Err(e) => {
-                eprint(e.to_string());
-                return;
+                eprint(format!("Could not run '{}': {}", self.command, e));
+                std::process::exit(126);
             }
         }
     }
 }
 
 
+#[derive(Args, Debug)]
+pub struct StartProject {
+
+    /// Start the project again whenever a project file changes
+    #[clap(short = 'w', long = "watch", takes_value = false)]
+    pub watch: bool,
+
+    /// Arguments for the entry point, after its default args
+    #[clap(allow_hyphen_values = true, multiple_values = true)]
+    pub args: Vec<String>,
+
+}
+
+
 #[derive(Args, Debug)]
 pub struct Installer {
     
@@ -424,6 +802,23 @@ pub struct Installer {
     #[clap(short = 'r', long = "requirements", default_value = "")]
     pub requirements: String,
 
+    /// Refuse to install packages without a recorded sha256 hash
+    #[clap(long = "require-hashes", takes_value = false)]
+    pub require_hashes: bool,
+
+}
+
+/// with `--require-hashes` every package must have at least one known hash
+fn check_hashes(resolution: &Resolution, required: bool) -> bool {
+    if !required {
+        return true;
+    }
+    let missing = resolution.missing_hashes();
+    if !missing.is_empty() {
+        eprint(format!("No hashes known for: {}", missing.join(", ")));
+        return false;
+    }
+    true
 }
 
 impl Installer {
@@ -432,7 +827,7 @@ impl Installer {
         if !check_venv_dir_exists() {
             wprint("Could not find venv directory".to_owned());
             if ask_if_create_venv() {
-                if setup_venv("./venv".to_owned()).is_err() {
+                if setup_venv(venv_dir()).is_err() {
                     eprint("Failed to setup venv".to_owned());
                     return;
                 }
@@ -455,12 +850,40 @@ impl Installer {
             return;
         } 
 
-        let mut pkg_names = Vec::new();
-        for line in req_file.lines() {
-            if line.contains("#") {
+        let mut file_reqs = Vec::new();
+        let mut file_hashes: HashMap<String, Vec<String>> = HashMap::new();
+        let mut require_hashes = self.require_hashes;
+        for line in req_file.replace("\\\r\n", " ").replace("\\\n", " ").lines() {
+            let line = line.split(" #").next().unwrap_or("").trim();
+            if line == "--require-hashes" {
+                require_hashes = true;
+                continue;
+            }
+            // skip comments, blank lines and other pip options
+            if line.is_empty() || line.starts_with('#') || line.starts_with('-') {
                 continue;
             }
-            pkg_names.push(line.to_string());
+            let (spec, options) = match line.find(" --") {
+                Some(pos) => line.split_at(pos),
+                None => (line, ""),
+            };
+            let hashes: Vec<String> = options
+                .split_whitespace()
+                .filter_map(|o| o.strip_prefix("--hash=sha256:"))
+                .map(|h| h.to_lowercase())
+                .collect();
+            match Requirement::parse(spec.trim()) {
+                Some(req) => {
+                    if !hashes.is_empty() {
+                        file_hashes.insert(req.key(), hashes);
+                    }
+                    file_reqs.push(req);
+                },
+                None => {
+                    eprint(format!("Invalid requirement '{}'", spec.trim()));
+                    return;
+                }
+            }
         }
  
         let mut conf = match Config::load_from_file("project.toml") {
@@ -471,31 +894,52 @@ impl Installer {
             }
         };
 
+        let keep = |reqs: Vec<Requirement>| -> Vec<Requirement> {
+            let mut reqs: Vec<Requirement> = reqs
+                .into_iter()
+                .filter(|r| !file_reqs.iter().any(|f| f.key() == r.key()))
+                .collect();
+            reqs.extend(file_reqs.iter().cloned());
+            reqs
+        };
+        let roots = keep(conf.lock_requirements());
+        let installed_roots = keep(conf.install_requirements());
 
-        for pkg_name in pkg_names.iter() {
-            let (vname, mut ver) = parse_version(pkg_name.clone());
+        let mut resolution = match resolve_requirements(&roots) {
+            Ok(resolution) => resolution,
+            Err(_) => return,
+        };
+        // hashes pinned in the requirements file take precedence over the index
+        for (key, hashes) in file_hashes.into_iter() {
+            if let Some(pkg) = resolution.packages.get_mut(&key) {
+                pkg.hashes = hashes;
+            }
+        }
+        let install = resolution.subset(&installed_roots);
+        if !check_hashes(&install, require_hashes) {
+            return;
+        }
+        let tx = match apply_resolution(&install, require_hashes) {
+            Ok(tx) => tx,
+            Err(_) => return,
+        };
+        if !check_resolution_licenses(&conf, &install) {
+            tx.rollback();
+            return;
+        }
 
-            if install_package(pkg_name.clone()) {
-                if ver.len() == 0 {
-                    // if no version, set to latest
-                    let v = get_pkg_version(&pkg_name);
-                    if v.is_err() {
-                        return;
-                    }
-                    ver = v.unwrap();
-                } 
-                conf.packages.insert(vname.clone(), ver.clone());
-                match conf.write_to_file("project.toml") {
-                    Ok(_) => {
-                        iprint(format!("Package '{}' installed successfully", &vname));
-                    },
-                    Err(e) => {
-                        eprint(e.to_string());
-                        return;
-                    }
-                }
-            } else {
-                eprint(format!("Package '{}' failed to install", &vname));
+        let mut installed = vec![];
+        for req in file_reqs.iter() {
+            // requirements skipped by their environment marker are not resolved
+            if let Some(pkg) = resolution.packages.get(&req.key()) {
+                conf.set_package(req, &pkg.version.to_string());
+                installed.push(req.name.clone());
+            }
+        }
+        let lock = LockFile::from_resolution(&conf.lock_requirements(), &resolution);
+        if tx.commit(&conf, Some(&lock)).is_ok() {
+            for name in installed.iter() {
+                iprint(format!("Package '{}' installed successfully", name));
             }
         }
     }
@@ -528,7 +972,7 @@ impl Installer {
         if !check_venv_dir_exists() {
             wprint("Could not find venv directory".to_owned());
             if ask_if_create_venv() {
-                if setup_venv("./venv".to_owned()).is_err() {
+                if setup_venv(venv_dir()).is_err() {
                     eprint("Failed to setup venv".to_owned());
                     return;
                 }
@@ -538,22 +982,66 @@ impl Installer {
             }
         }
 
-        let mut cmd = Command::new("./venv/Scripts/pip.exe");
-        cmd.arg("install");
-        for (name, version) in conf.packages.iter() {
-            cmd.arg(format!("{}=={}", name, version));
+        // reuse the lockfile when it was resolved from the same requirements
+        let roots = conf.lock_requirements();
+        let resolution = match LockFile::load_from_file(LOCK_FILE) {
+            Ok(lock) if lock.matches(&roots) => lock.to_resolution(),
+            _ => match resolve_requirements(&roots) {
+                Ok(resolution) => resolution,
+                Err(_) => return,
+            },
+        };
+        // groups are locked but only installed when the active profile asks for them
+        let install = resolution.subset(&conf.install_requirements());
+        if !check_hashes(&install, self.require_hashes) {
+            return;
         }
 
-        let venv = cmd.spawn();
-
-        match venv {
-            Ok(mut o) => {
-                let _ = o.wait();
-            }
-            Err(e) => {
-                eprint("Failed to install packages".to_owned());
-                eprint(e.to_string());
-            }
+        let tx = match apply_resolution(&install, self.require_hashes) {
+            Ok(tx) => tx,
+            Err(_) => return,
+        };
+        if !check_resolution_licenses(&conf, &install) {
+            tx.rollback();
+            return;
+        }
+        let lock = LockFile::from_resolution(&roots, &resolution);
+        if tx.commit(&conf, Some(&lock)).is_ok() {
+            iprint("Packages installed successfully".to_owned());
         }
     }
-}
\ No newline at end of file
+}
+
+
+#[derive(Args, Debug)]
+pub struct GenRequirements {
+
+    /// File to write
+    #[clap(short = 'o', long = "output", default_value = "requirements.txt")]
+    pub output: String,
+
+    /// Also include the packages of a [groups.<name>] table
+    #[clap(short = 'g', long = "group")]
+    pub groups: Vec<String>,
+
+    /// Also include the packages of [groups.dev]
+    #[clap(long = "dev", takes_value = false)]
+    pub dev: bool,
+
+    /// Add the sha256 hashes from ppm.lock for pip's hash-checking mode, implies --include-transitive
+    #[clap(long = "with-hashes", takes_value = false)]
+    pub with_hashes: bool,
+
+    /// Pin every package in ppm.lock, not just the direct ones
+    #[clap(long = "include-transitive", takes_value = false)]
+    pub include_transitive: bool,
+
+    /// Output format: requirements or constraints
+    #[clap(long = "format", default_value = "requirements")]
+    pub format: String,
+
+    /// Don't write anything, fail if the file is out of date
+    #[clap(long = "check", takes_value = false)]
+    pub check: bool,
+
+}
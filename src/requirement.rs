@@ -0,0 +1,147 @@
+use std::fmt;
+
+/// normalize a package name as described in PEP 503
+pub fn normalize_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    let mut last_sep = false;
+    for c in name.trim().chars() {
+        if c == '-' || c == '_' || c == '.' {
+            if !last_sep {
+                normalized.push('-');
+            }
+            last_sep = true;
+        } else {
+            normalized.push(c.to_ascii_lowercase());
+            last_sep = false;
+        }
+    }
+    normalized
+}
+
+/// A single dependency specification (PEP 508), e.g. `requests[socks]>=2.0; python_version >= "3.7"`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requirement {
+    pub name: String,
+    pub extras: Vec<String>,
+    pub specifier: String,
+    pub marker: Option<String>,
+}
+
+impl Requirement {
+    pub fn parse(line: &str) -> Option<Requirement> {
+        let (spec_part, marker) = match line.split_once(';') {
+            Some((spec, marker)) => (spec.trim(), Some(marker.trim().to_string())),
+            None => (line.trim(), None),
+        };
+        if spec_part.is_empty() {
+            return None;
+        }
+
+        let name_end = spec_part
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'))
+            .unwrap_or(spec_part.len());
+        let name = spec_part[..name_end].to_string();
+        if name.is_empty() {
+            return None;
+        }
+        let mut rest = spec_part[name_end..].trim();
+
+        let mut extras = vec![];
+        if rest.starts_with('[') {
+            let close = rest.find(']')?;
+            extras = rest[1..close]
+                .split(',')
+                .map(|e| e.trim().to_string())
+                .filter(|e| !e.is_empty())
+                .collect();
+            rest = rest[close + 1..].trim();
+        }
+
+        // old style `name (>=1.0)` specifiers
+        let specifier = rest.trim_start_matches('(').trim_end_matches(')').trim();
+        let specifier = specifier.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()).collect::<Vec<_>>().join(",");
+
+        Some(Requirement {
+            name,
+            extras,
+            specifier,
+            marker: marker.filter(|m| !m.is_empty()),
+        })
+    }
+
+    pub fn key(&self) -> String {
+        normalize_name(&self.name)
+    }
+
+    /// true if the requirement only applies when an extra is requested
+    pub fn is_extra_only(&self) -> bool {
+        match &self.marker {
+            Some(marker) => crate::markers::uses_extra(marker),
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.extras.is_empty() {
+            write!(f, "[{}]", self.extras.join(","))?;
+        }
+        write!(f, "{}", self.specifier)?;
+        if let Some(marker) = &self.marker {
+            write!(f, "; {}", marker)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Requirement {
+        Requirement::parse(line).unwrap()
+    }
+
+    #[test]
+    fn normalizes_names() {
+        assert_eq!(normalize_name("Foo.Bar__baz"), "foo-bar-baz");
+        assert_eq!(normalize_name(" zope.interface "), "zope-interface");
+        assert_eq!(parse("Django_REST-framework").key(), "django-rest-framework");
+    }
+
+    #[test]
+    fn parses_name_extras_specifier_and_marker() {
+        let req = parse("requests[socks, security] >=2.0, <3 ; python_version >= \"3.7\"");
+        assert_eq!(req.name, "requests");
+        assert_eq!(req.extras, vec!["socks", "security"]);
+        assert_eq!(req.specifier, ">=2.0,<3");
+        assert_eq!(req.marker.as_deref(), Some("python_version >= \"3.7\""));
+        assert_eq!(req.to_string(), "requests[socks,security]>=2.0,<3; python_version >= \"3.7\"");
+    }
+
+    #[test]
+    fn parses_bare_and_old_style_requirements() {
+        assert_eq!(parse("flask"), Requirement { name: "flask".to_string(), extras: vec![], specifier: String::new(), marker: None });
+        assert_eq!(parse("six (>=1.10)").specifier, ">=1.10");
+        assert_eq!(parse("numpy;").marker, None);
+        assert_eq!(parse("pkg==1.0").to_string(), "pkg==1.0");
+    }
+
+    #[test]
+    fn rejects_lines_without_a_name() {
+        assert!(Requirement::parse("").is_none());
+        assert!(Requirement::parse("; python_version < '3'").is_none());
+        assert!(Requirement::parse(">=1.0").is_none());
+        assert!(Requirement::parse("pkg[extra").is_none());
+    }
+
+    #[test]
+    fn detects_extra_only_requirements() {
+        assert!(parse("pysocks; extra == 'socks'").is_extra_only());
+        assert!(!parse("colorama; sys_platform == 'win32'").is_extra_only());
+        assert!(!parse("idna").is_extra_only());
+    }
+}
@@ -0,0 +1,466 @@
+use colored::*;
+use std::collections::{BTreeMap, BTreeSet};
+use crate::index::{Artifact, PackageIndex};
+use crate::markers::MarkerEnv;
+use crate::requirement::{normalize_name, Requirement};
+use crate::version::{SpecifierSet, Version};
+use crate::utils::*;
+
+// give up instead of backtracking forever on pathological graphs
+const MAX_ROUNDS: usize = 5000;
+
+/// A package picked by the resolver
+#[derive(Debug, Clone)]
+pub struct ResolvedPackage {
+    pub name: String,
+    pub version: Version,
+    pub dependencies: Vec<Requirement>,
+    pub artifacts: Vec<Artifact>,
+    /// sha256 of every acceptable artifact, empty if unknown
+    pub hashes: Vec<String>,
+}
+
+/// A consistent set of packages satisfying every requirement
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub packages: BTreeMap<String, ResolvedPackage>,
+}
+
+impl Resolution {
+    /// packages without any recorded artifact hash
+    pub fn missing_hashes(&self) -> Vec<String> {
+        self.packages
+            .values()
+            .filter(|p| p.hashes.is_empty())
+            .map(|p| p.name.clone())
+            .collect()
+    }
+
+    /// only the packages `roots` depend on, directly or not
+    pub fn subset(&self, roots: &[Requirement]) -> Resolution {
+        let mut packages = BTreeMap::new();
+        let mut stack: Vec<String> = roots.iter().map(|r| r.key()).collect();
+        while let Some(key) = stack.pop() {
+            if packages.contains_key(&key) {
+                continue;
+            }
+            if let Some(pkg) = self.packages.get(&key) {
+                stack.extend(pkg.dependencies.iter().map(|d| d.key()));
+                packages.insert(key, pkg.clone());
+            }
+        }
+        Resolution { packages }
+    }
+
+    /// package key and target version of every package, for a transaction
+    pub fn targets(&self) -> Vec<(String, Option<String>)> {
+        self.packages
+            .iter()
+            .map(|(key, p)| (key.clone(), Some(p.version.to_string())))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Constraint {
+    parent: String,
+    req: Requirement,
+}
+
+#[derive(Debug, Clone)]
+struct Pinned {
+    name: String,
+    version: Version,
+    requires_dist: Vec<Requirement>,
+    artifacts: Vec<Artifact>,
+    extras: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct State {
+    pinned: BTreeMap<String, Pinned>,
+    constraints: Vec<Constraint>,
+}
+
+impl State {
+    fn constraints_on(&self, key: &str) -> Vec<&Constraint> {
+        self.constraints.iter().filter(|c| c.req.key() == key).collect()
+    }
+
+    fn requested_extras(&self, key: &str) -> BTreeSet<String> {
+        self.constraints_on(key)
+            .iter()
+            .flat_map(|c| c.req.extras.iter().map(|e| normalize_name(e)))
+            .collect()
+    }
+}
+
+enum Failure {
+    Conflict,
+    Fatal(String),
+}
+
+/// Backtracking resolver working on index metadata
+pub struct Resolver<'a> {
+    index: &'a mut PackageIndex,
+    env: MarkerEnv,
+    python: Option<Version>,
+    conflicts: Vec<String>,
+    rounds: usize,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(index: &'a mut PackageIndex, env: MarkerEnv) -> Resolver<'a> {
+        let python = env.python_version();
+        Resolver {
+            index,
+            env,
+            python,
+            conflicts: vec![],
+            rounds: 0,
+        }
+    }
+
+    fn add_conflict(&mut self, reason: String) {
+        if !self.conflicts.contains(&reason) {
+            self.conflicts.push(reason);
+        }
+    }
+
+    /// requirements of a package that apply to this environment and the requested extras
+    fn applicable(&self, reqs: &[Requirement], extras: &BTreeSet<String>, only_extras: bool) -> Vec<Requirement> {
+        reqs.iter()
+            .filter(|r| match &r.marker {
+                None => !only_extras,
+                Some(marker) => {
+                    let base = !only_extras && !r.is_extra_only() && self.env.evaluate(marker, None);
+                    base || (r.is_extra_only() && extras.iter().any(|e| self.env.evaluate(marker, Some(e))))
+                }
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn python_ok(&self, requires_python: &Option<String>) -> bool {
+        match (&self.python, requires_python) {
+            (Some(python), Some(spec)) => match SpecifierSet::parse(spec) {
+                Some(set) => set.contains(python),
+                None => true,
+            },
+            _ => true,
+        }
+    }
+
+    /// add new constraints, failing if one contradicts an already pinned package
+    fn add_constraints(&mut self, state: &mut State, parent: &str, reqs: Vec<Requirement>) -> Result<(), ()> {
+        let mut queue: Vec<(String, Requirement)> = reqs.into_iter().map(|r| (parent.to_string(), r)).collect();
+        while let Some((parent, req)) = queue.pop() {
+            let key = req.key();
+            if let Some(pin) = state.pinned.get(&key).cloned() {
+                let set = SpecifierSet::parse(&req.specifier).unwrap_or_default();
+                if !set.contains(&pin.version) {
+                    let holders = state.constraints_on(&key)
+                        .iter()
+                        .map(|c| format!("{} requires {}", c.parent, describe(&c.req)))
+                        .collect::<Vec<String>>();
+                    self.add_conflict(format!(
+                        "{} requires {}, but {} {} was selected ({})",
+                        parent, describe(&req), pin.name, pin.version, holders.join(", ")
+                    ));
+                    return Err(());
+                }
+            }
+            state.constraints.push(Constraint { parent: parent.clone(), req: req.clone() });
+
+            // extras requested after the package was pinned pull in more requirements
+            if let Some(pin) = state.pinned.get(&key).cloned() {
+                let missing: BTreeSet<String> = state.requested_extras(&key).difference(&pin.extras).cloned().collect();
+                if !missing.is_empty() {
+                    let extra_reqs = self.applicable(&pin.requires_dist, &missing, true);
+                    let pin_parent = format!("{}[{}] {}", pin.name, missing.iter().cloned().collect::<Vec<_>>().join(","), pin.version);
+                    state.pinned.get_mut(&key).unwrap().extras.extend(missing);
+                    queue.extend(extra_reqs.into_iter().map(|r| (pin_parent.clone(), r)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn candidates(&mut self, key: &str, state: &State) -> Result<Vec<Version>, Failure> {
+        let constraints = state.constraints_on(key);
+        let name = constraints[0].req.name.clone();
+        let releases = self.index.releases(&name).map_err(Failure::Fatal)?;
+
+        let mut sets = vec![];
+        for c in constraints.iter() {
+            match SpecifierSet::parse(&c.req.specifier) {
+                Some(set) => sets.push(set),
+                None => return Err(Failure::Fatal(format!("Invalid version specifier '{}' for {} (from {})", c.req.specifier, c.req.name, c.parent))),
+            }
+        }
+        let exact_pin = |v: &Version| sets.iter().any(|s| s.specifiers.iter().any(|sp| sp.op == "==" && sp.contains(v)));
+        let allow_pre = sets.iter().any(|s| s.allows_prereleases());
+
+        let matching: Vec<_> = releases
+            .iter()
+            .filter(|r| !r.yanked || exact_pin(&r.version))
+            .filter(|r| self.python_ok(&r.requires_python))
+            .filter(|r| sets.iter().all(|s| s.contains(&r.version)))
+            .collect();
+        let finals: Vec<Version> = matching.iter().filter(|r| allow_pre || !r.version.is_prerelease()).map(|r| r.version.clone()).collect();
+        if !finals.is_empty() {
+            return Ok(finals);
+        }
+        // only pre-releases satisfy the requirements, PEP 440 says to use them then
+        Ok(matching.iter().map(|r| r.version.clone()).collect())
+    }
+
+    fn explain_no_candidates(&mut self, key: &str, state: &State) {
+        let display = self.index.display_name(key);
+        let reasons = state.constraints_on(key)
+            .iter()
+            .map(|c| format!("{} requires {}", c.parent, describe(&c.req)))
+            .collect::<Vec<String>>();
+        let python = match &self.python {
+            Some(p) => format!(" for Python {}", p),
+            None => String::new(),
+        };
+        self.add_conflict(format!("no version of {}{} satisfies all of: {}", display, python, reasons.join("; ")));
+    }
+
+    fn solve(&mut self, state: State) -> Result<State, Failure> {
+        self.rounds += 1;
+        if self.rounds > MAX_ROUNDS {
+            return Err(Failure::Fatal("Resolution is taking too long, try pinning more package versions".to_owned()));
+        }
+
+        let next = state.constraints.iter().map(|c| c.req.key()).find(|k| !state.pinned.contains_key(k));
+        let key = match next {
+            Some(key) => key,
+            None => return Ok(state),
+        };
+
+        let candidates = self.candidates(&key, &state)?;
+        if candidates.is_empty() {
+            self.explain_no_candidates(&key, &state);
+            return Err(Failure::Conflict);
+        }
+
+        let extras = state.requested_extras(&key);
+        for version in candidates {
+            let name = self.index.display_name(&key);
+            let meta = self.index.metadata(&name, &version.to_string()).map_err(Failure::Fatal)?;
+            if !self.python_ok(&meta.requires_python) {
+                continue;
+            }
+
+            let mut next_state = state.clone();
+            next_state.pinned.insert(key.clone(), Pinned {
+                name: name.clone(),
+                version: version.clone(),
+                requires_dist: meta.requires_dist.clone(),
+                artifacts: meta.files.clone(),
+                extras: extras.clone(),
+            });
+            let deps = self.applicable(&meta.requires_dist, &extras, false);
+            if self.add_constraints(&mut next_state, &format!("{} {}", name, version), deps).is_err() {
+                continue;
+            }
+            match self.solve(next_state) {
+                Ok(solved) => return Ok(solved),
+                Err(Failure::Conflict) => continue,
+                Err(fatal) => return Err(fatal),
+            }
+        }
+        Err(Failure::Conflict)
+    }
+
+    /// find versions for `roots` and everything they depend on
+    pub fn resolve(&mut self, roots: &[Requirement]) -> Result<Resolution, String> {
+        let mut state = State::default();
+        let roots = roots
+            .iter()
+            .filter(|r| r.marker.as_ref().map(|m| self.env.evaluate(m, None)).unwrap_or(true))
+            .cloned()
+            .collect();
+        if self.add_constraints(&mut state, "project", roots).is_err() {
+            return Err(self.conflicts.join("\n"));
+        }
+
+        match self.solve(state) {
+            Ok(solved) => {
+                let packages = solved.pinned
+                    .iter()
+                    .map(|(key, pin)| {
+                        let dependencies = self.applicable(&pin.requires_dist, &pin.extras, false);
+                        (key.clone(), ResolvedPackage {
+                            name: pin.name.clone(),
+                            version: pin.version.clone(),
+                            dependencies,
+                            artifacts: pin.artifacts.clone(),
+                            hashes: pin.artifacts.iter().filter_map(|a| a.sha256.clone()).collect(),
+                        })
+                    })
+                    .collect();
+                Ok(Resolution { packages })
+            },
+            Err(Failure::Fatal(e)) => Err(e),
+            Err(Failure::Conflict) => {
+                // the most specific reasons were recorded last
+                let reasons = self.conflicts.iter().rev().take(8).rev().cloned().collect::<Vec<String>>();
+                Err(format!("Could not find versions that satisfy all requirements:\n  - {}", reasons.join("\n  - ")))
+            },
+        }
+    }
+}
+
+fn describe(req: &Requirement) -> String {
+    let mut name = req.name.clone();
+    if !req.extras.is_empty() {
+        name = format!("{}[{}]", name, req.extras.join(","));
+    }
+    if req.specifier.is_empty() {
+        format!("{} (any version)", name)
+    } else {
+        format!("{} {}", name, req.specifier)
+    }
+}
+
+/// resolve requirements for the project venv, printing progress and conflicts
+pub fn resolve_requirements(roots: &[Requirement]) -> Result<Resolution, ()> {
+    let python = venv_python();
+    let env = match MarkerEnv::from_python(&python) {
+        Ok(env) => env,
+        Err(e) => {
+            eprint(format!("Could not inspect the python interpreter: {}", e));
+            return Err(());
+        }
+    };
+
+    iprint("Resolving dependencies...".to_string());
+    let mut index = PackageIndex::new();
+    let mut resolver = Resolver::new(&mut index, env);
+    match resolver.resolve(roots) {
+        Ok(resolution) => {
+            println!("{} {} packages", "Resolved".green().bold(), resolution.packages.len());
+            Ok(resolution)
+        },
+        Err(e) => {
+            eprint("Dependency resolution failed".to_owned());
+            println!("{}", e);
+            Err(())
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env() -> MarkerEnv {
+        let values = [("sys_platform", "linux"), ("python_version", "3.11"), ("python_full_version", "3.11.4")];
+        MarkerEnv { values: values.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect() }
+    }
+
+    fn reqs(lines: &[&str]) -> Vec<Requirement> {
+        lines.iter().map(|l| Requirement::parse(l).unwrap()).collect()
+    }
+
+    fn resolve(index: &[(&str, &str, &[&str])], roots: &[&str]) -> Result<Resolution, String> {
+        let mut index = PackageIndex::fake(index);
+        Resolver::new(&mut index, env()).resolve(&reqs(roots))
+    }
+
+    fn versions(resolution: &Resolution) -> Vec<String> {
+        resolution.packages.values().map(|p| format!("{}=={}", p.name, p.version)).collect()
+    }
+
+    #[test]
+    fn picks_the_newest_versions_of_everything_needed() {
+        let index: &[(&str, &str, &[&str])] = &[
+            ("app-lib", "1.0", &["Helper>=1"]),
+            ("app-lib", "1.1", &["Helper>=1.5"]),
+            ("Helper", "1.0", &[]),
+            ("Helper", "2.0", &[]),
+            ("unused", "1.0", &[]),
+        ];
+        let resolution = resolve(index, &["App_Lib"]).unwrap();
+        assert_eq!(versions(&resolution), vec!["app-lib==1.1", "Helper==2.0"]);
+        assert_eq!(resolution.packages["app-lib"].dependencies, reqs(&["Helper>=1.5"]));
+        assert_eq!(resolution.packages["helper"].hashes.len(), 1);
+    }
+
+    #[test]
+    fn backtracks_out_of_a_conflict() {
+        let index: &[(&str, &str, &[&str])] = &[
+            ("a", "1.0", &["b==1.0"]),
+            ("a", "2.0", &["b==2.0"]),
+            ("b", "1.0", &[]),
+            ("b", "2.0", &[]),
+            ("c", "1.0", &["b<2"]),
+        ];
+        let resolution = resolve(index, &["a", "c"]).unwrap();
+        assert_eq!(versions(&resolution), vec!["a==1.0", "b==1.0", "c==1.0"]);
+    }
+
+    #[test]
+    fn explains_unsatisfiable_requirements() {
+        let index: &[(&str, &str, &[&str])] = &[
+            ("a", "1.0", &[]),
+            ("a", "2.0", &[]),
+            ("b", "1.0", &["a>=2"]),
+        ];
+        let err = resolve(index, &["a==1.0", "b"]).unwrap_err();
+        assert!(err.contains("Could not find versions"), "{}", err);
+        assert!(err.contains("b 1.0 requires a >=2"), "{}", err);
+    }
+
+    #[test]
+    fn follows_extras_and_markers() {
+        let index: &[(&str, &str, &[&str])] = &[
+            ("web", "1.0", &["click; extra == 'cli'", "pywin32; sys_platform == 'win32'", "typing; python_version < '3.8'"]),
+            ("click", "8.0", &[]),
+            ("pywin32", "306", &[]),
+            ("typing", "3.7", &[]),
+        ];
+        assert_eq!(versions(&resolve(index, &["web"]).unwrap()), vec!["web==1.0"]);
+        assert_eq!(versions(&resolve(index, &["web[cli]"]).unwrap()), vec!["click==8.0", "web==1.0"]);
+        // an extra asked for by a later requirement still pulls its dependencies in
+        let index: &[(&str, &str, &[&str])] = &[
+            ("web", "1.0", &["click; extra == 'cli'"]),
+            ("tool", "1.0", &["web[cli]"]),
+            ("click", "8.0", &[]),
+        ];
+        assert_eq!(versions(&resolve(index, &["web", "tool"]).unwrap()), vec!["click==8.0", "tool==1.0", "web==1.0"]);
+    }
+
+    #[test]
+    fn skips_roots_whose_marker_does_not_apply() {
+        let index: &[(&str, &str, &[&str])] = &[("pywin32", "306", &[])];
+        let resolution = resolve(index, &["pywin32==306; sys_platform == 'win32'"]).unwrap();
+        assert!(resolution.packages.is_empty());
+    }
+
+    #[test]
+    fn prefers_final_releases() {
+        let index: &[(&str, &str, &[&str])] = &[("a", "1.0", &[]), ("a", "2.0b1", &[])];
+        assert_eq!(versions(&resolve(index, &["a"]).unwrap()), vec!["a==1.0"]);
+        assert_eq!(versions(&resolve(index, &["a>=2.0b1"]).unwrap()), vec!["a==2.0b1"]);
+        let index: &[(&str, &str, &[&str])] = &[("a", "2.0b1", &[])];
+        assert_eq!(versions(&resolve(index, &["a"]).unwrap()), vec!["a==2.0b1"]);
+    }
+
+    #[test]
+    fn subset_keeps_what_the_roots_reach() {
+        let index: &[(&str, &str, &[&str])] = &[
+            ("app", "1.0", &["lib"]),
+            ("lib", "1.0", &[]),
+            ("pytest", "7.0", &["pluggy"]),
+            ("pluggy", "1.0", &[]),
+        ];
+        let resolution = resolve(index, &["app", "pytest"]).unwrap();
+        let subset = resolution.subset(&reqs(&["app"]));
+        assert_eq!(versions(&subset), vec!["app==1.0", "lib==1.0"]);
+    }
+}
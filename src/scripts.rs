@@ -0,0 +1,420 @@
+use colored::*;
+use std::{io::{BufRead, BufReader}, process::{Command, Stdio}, thread, time::{Duration, Instant}};
+use crate::environment::apply_project_env;
+use crate::process::{received_signal, run_foreground, wait_forwarding};
+use crate::settings::{Config, ScriptCommand, ScriptTable};
+use crate::utils::*;
+
+fn default_shell() -> &'static str {
+    if cfg!(target_os = "windows") { "cmd" } else { "bash" }
+}
+
+fn shell_command(shell: &str, line: &str) -> Command {
+    let flag = match shell {
+        "cmd" => "/C",
+        "powershell" | "pwsh" => "-Command",
+        _ => "-c",
+    };
+    let mut cmd = Command::new(shell);
+    cmd.arg(flag);
+    // cmd doesn't parse its arguments like other programs, hand it the line as it is
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        if shell == "cmd" {
+            cmd.raw_arg(line);
+            return cmd;
+        }
+    }
+    cmd.arg(line);
+    cmd
+}
+
+/// build a command from arguments, substituting `{args}`
+fn argv_command(argv: &[String], args: &[String], append: bool) -> Result<Command, String> {
+    let mut full = vec![];
+    for arg in argv.iter() {
+        if arg == "{args}" {
+            full.extend(args.iter().cloned());
+        } else {
+            full.push(arg.replace("{args}", &args.join(" ")));
+        }
+    }
+    if append {
+        full.extend(args.iter().cloned());
+    }
+    let (program, rest) = full.split_first().ok_or_else(|| "empty command".to_string())?;
+    let mut cmd = Command::new(program);
+    cmd.args(rest);
+    Ok(cmd)
+}
+
+/// expand the `{args}` of a command line for a shell, quoted the way that shell reads them
+fn shell_line(shell: &str, line: &str, args: &[String]) -> String {
+    let args = args.iter().map(|a| shell_quote_for(shell, a)).collect::<Vec<String>>().join(" ");
+    if line.contains("{args}") {
+        line.replace("{args}", &args)
+    } else if args.is_empty() {
+        line.to_string()
+    } else {
+        format!("{} {}", line, args)
+    }
+}
+
+fn prepare(cmd: &mut Command, table: &ScriptTable) {
+    apply_project_env(cmd);
+    activate_venv(cmd);
+    if let Some(cwd) = &table.cwd {
+        cmd.current_dir(cwd);
+    }
+    cmd.envs(&table.env);
+}
+
+/// What a step does: run one command, or several step lists at the same time
+pub enum Action {
+    Run(Command),
+    Parallel(Vec<(String, Vec<Step>)>),
+}
+
+/// A command to run on behalf of a script
+pub struct Step {
+    pub script: String,
+    pub action: Action,
+}
+
+const PREFIX_COLORS: [Color; 6] = [Color::Cyan, Color::Magenta, Color::Yellow, Color::Green, Color::Blue, Color::Red];
+
+/// run a command to completion, returning its exit code, with every output line
+/// prefixed by `prefix` if there is one
+fn run_command(cmd: &mut Command, prefix: Option<&ColoredString>) -> i32 {
+    let prefix = match prefix {
+        Some(prefix) => prefix,
+        None => return match run_foreground(cmd) {
+            Ok(code) => code,
+            Err(e) => {
+                eprint(format!("Could not run '{}': {}", cmd.get_program().to_string_lossy(), e));
+                1
+            }
+        },
+    };
+
+    let mut child = match cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            println!("{} could not run '{}': {}", prefix, cmd.get_program().to_string_lossy(), e);
+            return 1;
+        }
+    };
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                println!("{} {}", prefix, line);
+            }
+        });
+        scope.spawn(|| {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                eprintln!("{} {}", prefix, line);
+            }
+        });
+    });
+    wait_forwarding(&mut child)
+}
+
+/// run steps in order, stopping at the first failure and returning its exit code
+fn run_steps(steps: Vec<Step>, prefix: Option<&ColoredString>) -> i32 {
+    let composite = steps.windows(2).any(|w| w[0].script != w[1].script);
+    let mut current = String::new();
+    for step in steps {
+        if composite && prefix.is_none() && step.script != current {
+            println!("{}", format!("> {}", step.script).bright_black());
+            current = step.script.clone();
+        }
+        let code = match step.action {
+            Action::Run(mut cmd) => run_command(&mut cmd, prefix),
+            Action::Parallel(groups) => run_parallel(groups),
+        };
+        // a command may handle Ctrl+C and exit cleanly, the run still stops there
+        let code = match received_signal() {
+            Some(signal) if code == 0 => 128 + signal,
+            _ => code,
+        };
+        if code != 0 {
+            if composite && prefix.is_none() {
+                eprint(format!("Script '{}' failed with exit code {}", step.script, code));
+            }
+            return code;
+        }
+    }
+    0
+}
+
+/// run step lists concurrently with prefixed output, then print how each went
+fn run_parallel(groups: Vec<(String, Vec<Step>)>) -> i32 {
+    let width = groups.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let results: Vec<(String, i32, Duration)> = thread::scope(|scope| {
+        let handles: Vec<_> = groups
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, steps))| {
+                let prefix = format!("{:width$} |", name, width = width).color(PREFIX_COLORS[i % PREFIX_COLORS.len()]);
+                scope.spawn(move || {
+                    let start = Instant::now();
+                    let code = run_steps(steps, Some(&prefix));
+                    (name, code, start.elapsed())
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    println!();
+    println!("{}", "Summary".green().bold());
+    for (name, code, duration) in results.iter() {
+        let status = if *code == 0 { "ok    ".green() } else { "failed".bright_red() };
+        println!("  {:width$}  {}  exit {:<3}  {:.2}s", name.bright_cyan(), status, code, duration.as_secs_f64(), width = width);
+    }
+    results.iter().map(|(_, code, _)| *code).find(|code| *code != 0).unwrap_or(0)
+}
+
+/// Runs the scripts defined in project.toml
+pub struct ScriptRunner<'a> {
+    conf: &'a Config,
+}
+
+impl<'a> ScriptRunner<'a> {
+    pub fn new(conf: &'a Config) -> ScriptRunner<'a> {
+        ScriptRunner { conf }
+    }
+
+    /// `pre<name>`/`post<name>` hooks, hooks themselves don't get hooks
+    fn hook(&self, kind: &str, name: &str) -> Option<String> {
+        let is_hook = ["pre", "post"].iter().any(|p| name.strip_prefix(p).map(|n| self.conf.script(n).is_some()).unwrap_or(false));
+        let hook = format!("{}{}", kind, name);
+        if !is_hook && self.conf.script(&hook).is_some() { Some(hook) } else { None }
+    }
+
+    /// work out every command `name` runs, in order, before running any of them
+    fn plan(&self, name: &str, args: &[String], stack: &mut Vec<String>, done: &mut Vec<String>, steps: &mut Vec<Step>) -> Result<(), String> {
+        if stack.iter().any(|s| s == name) {
+            return Err(format!("Script cycle: {} -> {}", stack.join(" -> "), name));
+        }
+        let table = match self.conf.script(name) {
+            Some(script) => script.table(),
+            None if stack.is_empty() => return Err(format!("Script with name '{}' does not exist", name)),
+            None => return Err(format!("Script '{}' refers to '{}', which does not exist", stack.last().unwrap(), name)),
+        };
+        stack.push(name.to_string());
+
+        for dep in table.depends.iter() {
+            if !done.contains(dep) {
+                self.plan(dep, &[], stack, done, steps)?;
+            }
+        }
+        if let Some(hook) = self.hook("pre", name) {
+            self.plan(&hook, &[], stack, done, steps)?;
+        }
+
+        let step = |cmd: Result<Command, String>| -> Result<Step, String> {
+            let mut cmd = cmd.map_err(|e| format!("Invalid script '{}': {}", name, e))?;
+            prepare(&mut cmd, &table);
+            Ok(Step { script: name.to_string(), action: Action::Run(cmd) })
+        };
+        match &table.cmd {
+            None if table.depends.is_empty() => return Err(format!("Invalid script '{}': it has no cmd", name)),
+            None => {},
+            Some(ScriptCommand::Line(line)) => match table.shell.as_deref().unwrap_or(default_shell()) {
+                "none" => {
+                    let argv = split_args(line);
+                    steps.push(step(argv.and_then(|argv| argv_command(&argv, args, !line.contains("{args}"))))?);
+                },
+                shell => steps.push(step(Ok(shell_command(shell, &shell_line(shell, line, args))))?),
+            },
+            // arguments go to `{args}` or the last command
+            Some(ScriptCommand::Sequence(lines)) => {
+                let parallel = table.parallel.unwrap_or(false);
+                let placeholder = lines.iter().any(|l| l.contains("{args}"));
+                let mut groups = vec![];
+                for (i, line) in lines.iter().enumerate() {
+                    // entries running side by side can't rely on each other's dependencies
+                    let mut group_done = done.clone();
+                    let (target, target_done) = if parallel { (&mut vec![], &mut group_done) } else { (&mut *steps, &mut *done) };
+                    if self.conf.script(line.trim()).is_some() {
+                        self.plan(line.trim(), &[], stack, target_done, target)?;
+                    } else {
+                        let append = !placeholder && i + 1 == lines.len();
+                        let line_args = if placeholder || append { args } else { &[] };
+                        let argv = split_args(line);
+                        target.push(step(argv.and_then(|argv| argv_command(&argv, line_args, append)))?);
+                    }
+                    if parallel {
+                        groups.push((line.trim().to_string(), std::mem::take(target)));
+                    }
+                }
+                if parallel {
+                    steps.push(Step { script: name.to_string(), action: Action::Parallel(groups) });
+                }
+            },
+        }
+
+        if let Some(hook) = self.hook("post", name) {
+            self.plan(&hook, &[], stack, done, steps)?;
+        }
+        stack.pop();
+        done.push(name.to_string());
+        Ok(())
+    }
+
+    /// the commands running `name` takes, with its dependencies and hooks
+    pub fn steps(&self, name: &str, args: &[String]) -> Result<Vec<Step>, String> {
+        let mut steps = vec![];
+        self.plan(name, args, &mut vec![], &mut vec![], &mut steps)?;
+        Ok(steps)
+    }
+
+    /// run a script by name, stopping at the first command that fails and returning its exit code
+    pub fn run(&self, name: &str, args: &[String]) -> i32 {
+        match self.steps(name, args) {
+            Ok(steps) => run_steps(steps, None),
+            Err(e) => {
+                eprint(e);
+                1
+            }
+        }
+    }
+
+    /// run several scripts at the same time
+    pub fn run_parallel(&self, names: &[String]) -> i32 {
+        let mut groups = vec![];
+        for name in names.iter() {
+            match self.steps(name, &[]) {
+                Ok(steps) => groups.push((name.clone(), steps)),
+                Err(e) => {
+                    eprint(e);
+                    return 1;
+                }
+            }
+        }
+        run_parallel(groups)
+    }
+}
+
+/// run a command line with the default shell, the way scripts are run
+pub fn run_line(line: &str) -> i32 {
+    let mut cmd = shell_command(default_shell(), line);
+    prepare(&mut cmd, &ScriptTable::default());
+    run_command(&mut cmd, None)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn config(scripts: &str) -> Config {
+        toml::from_str(&format!("[project]\nname = \"p\"\nversion = \"0.1\"\ndescription = \"\"\n\n[packages]\n\n[scripts]\n{}", scripts)).unwrap()
+    }
+
+    /// `script: command` for every step, shell commands by their line
+    fn describe(steps: &[Step]) -> Vec<String> {
+        steps
+            .iter()
+            .map(|step| match &step.action {
+                Action::Run(cmd) => {
+                    let args: Vec<String> = cmd.get_args().map(|a| a.to_string_lossy().to_string()).collect();
+                    let program = cmd.get_program().to_string_lossy().to_string();
+                    let line = if program == default_shell() { args.last().cloned().unwrap_or_default() } else { [vec![program], args].concat().join(" ") };
+                    format!("{}: {}", step.script, line)
+                },
+                Action::Parallel(groups) => {
+                    let groups: Vec<String> = groups.iter().map(|(name, steps)| format!("{} [{}]", name, describe(steps).join(", "))).collect();
+                    format!("{}: parallel {}", step.script, groups.join(" | "))
+                },
+            })
+            .collect()
+    }
+
+    fn plan(conf: &Config, name: &str, args: &[&str]) -> Result<Vec<String>, String> {
+        ScriptRunner::new(conf).steps(name, &strings(args)).map(|steps| describe(&steps))
+    }
+
+    const SCRIPTS: &str = r#"
+build = "echo build"
+lint = "echo lint"
+prelint = "echo prelint"
+preprelint = "echo never"
+test = { cmd = "pytest {args}", depends = ["build", "lint"] }
+posttest = "echo cleanup"
+ci = ["lint", "test", "echo done"]
+checks = { cmd = ["lint", "echo inline"], parallel = true }
+raw = { cmd = "printf [%s] 'a b'", shell = "none" }
+loop1 = { cmd = "echo x", depends = ["loop2"] }
+loop2 = ["loop1"]
+broken = { depends = ["missing"] }
+empty = { shell = "sh" }
+"#;
+
+    #[test]
+    fn plans_dependencies_and_hooks_in_order() {
+        let conf = config(SCRIPTS);
+        assert_eq!(plan(&conf, "test", &["-k", "slow one"]).unwrap(), vec![
+            "build: echo build",
+            "prelint: echo prelint",
+            "lint: echo lint",
+            "test: pytest -k 'slow one'",
+            "posttest: echo cleanup",
+        ]);
+    }
+
+    #[test]
+    fn runs_each_script_once_in_sequences() {
+        let conf = config(SCRIPTS);
+        assert_eq!(plan(&conf, "ci", &["v"]).unwrap(), vec![
+            "prelint: echo prelint",
+            "lint: echo lint",
+            "build: echo build",
+            "test: pytest ",
+            "posttest: echo cleanup",
+            "ci: echo done v",
+        ]);
+    }
+
+    #[test]
+    fn plans_parallel_entries_separately() {
+        let conf = config(SCRIPTS);
+        assert_eq!(plan(&conf, "checks", &[]).unwrap(), vec![
+            "checks: parallel lint [prelint: echo prelint, lint: echo lint] | echo inline [checks: echo inline]",
+        ]);
+    }
+
+    #[test]
+    fn splits_scripts_without_a_shell() {
+        let conf = config(SCRIPTS);
+        assert_eq!(plan(&conf, "raw", &["$HOME"]).unwrap(), vec!["raw: printf [%s] a b $HOME"]);
+    }
+
+    #[test]
+    fn reports_cycles_and_missing_scripts() {
+        let conf = config(SCRIPTS);
+        assert_eq!(plan(&conf, "loop1", &[]).unwrap_err(), "Script cycle: loop1 -> loop2 -> loop1");
+        assert_eq!(plan(&conf, "broken", &[]).unwrap_err(), "Script 'broken' refers to 'missing', which does not exist");
+        assert_eq!(plan(&conf, "nope", &[]).unwrap_err(), "Script with name 'nope' does not exist");
+        assert_eq!(plan(&conf, "empty", &[]).unwrap_err(), "Invalid script 'empty': it has no cmd");
+    }
+
+    #[test]
+    fn puts_quoted_arguments_in_the_line() {
+        let args = strings(&["a b", "it's"]);
+        assert_eq!(shell_line("bash", "echo {args} end", &args), "echo 'a b' 'it'\\''s' end");
+        assert_eq!(shell_line("bash", "echo", &args), "echo 'a b' 'it'\\''s'");
+        assert_eq!(shell_line("bash", "echo {args}", &[]), "echo ");
+        assert_eq!(shell_line("bash", "echo", &[]), "echo");
+        assert_eq!(shell_line("pwsh", "Write-Output", &args), "Write-Output 'a b' 'it''s'");
+        assert_eq!(shell_line("cmd", "echo", &strings(&["%PATH%"])), "echo ^\"^%PATH^%^\"");
+    }
+}
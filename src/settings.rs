@@ -1,14 +1,23 @@
 use toml;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::{fs, io::Error};
+use std::collections::{BTreeMap, HashMap};
+use std::{fs, io::{Error, ErrorKind}, path::Path};
+use crate::requirement::{normalize_name, Requirement};
+use crate::utils::write_atomic;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Project {
     pub name: String,
     pub version: String,
     pub description: String,
+    /// a path, a module or `module:function`, see [`EntryTarget`]
+    #[serde(default)]
     pub main_script: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<EntryPoint>,
+    /// the venv directory, `./venv` when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub venv: Option<String>,
 }
 
 impl Project {
@@ -18,6 +27,253 @@ impl Project {
             version,
             description,
             main_script,
+            entry: None,
+            venv: None,
+        }
+    }
+}
+
+/// `[project.entry]`, how the project is started
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct EntryPoint {
+    /// a path, a module or `module:function`, `main_script` when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// arguments passed before the ones given to `ppm start`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+}
+
+impl EntryPoint {
+    pub fn target(&self) -> Result<EntryTarget, String> {
+        match &self.target {
+            Some(target) if !target.trim().is_empty() => Ok(EntryTarget::parse(target.trim())),
+            _ => Err("project.toml has no main_script or [project.entry] target".to_string()),
+        }
+    }
+}
+
+/// `myapp.cli`, a valid dotted Python name
+fn is_dotted_name(name: &str) -> bool {
+    !name.is_empty() && name.split('.').all(|part| {
+        part.starts_with(|c: char| c.is_alphabetic() || c == '_') && part.chars().all(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+/// What an entry point runs
+#[derive(Debug, PartialEq, Eq)]
+pub enum EntryTarget {
+    /// `src/main.py`
+    Path(String),
+    /// `myapp`, run with `python -m`
+    Module(String),
+    /// `myapp.cli:main`, called with no arguments, its return value is the exit code
+    Callable(String, String),
+}
+
+impl EntryTarget {
+    pub fn parse(target: &str) -> EntryTarget {
+        if let Some((module, function)) = target.rsplit_once(':') {
+            if is_dotted_name(module) && is_dotted_name(function) {
+                return EntryTarget::Callable(module.to_string(), function.to_string());
+            }
+        }
+        if is_dotted_name(target) && !target.ends_with(".py") && !std::path::Path::new(target).is_file() {
+            return EntryTarget::Module(target.to_string());
+        }
+        EntryTarget::Path(target.to_string())
+    }
+
+    /// the arguments that make python run the target
+    pub fn python_args(&self) -> Vec<String> {
+        match self {
+            EntryTarget::Path(path) => vec![path.clone()],
+            EntryTarget::Module(module) => vec!["-m".to_string(), module.clone()],
+            // both names are plain identifiers, safe to put in quotes
+            EntryTarget::Callable(module, function) => vec!["-c".to_string(), format!(
+                "import sys\nfrom importlib import import_module\nobj = import_module('{}')\nfor attr in '{}'.split('.'):\n    obj = getattr(obj, attr)\nsys.argv[0] = '{}'\nsys.exit(obj())",
+                module, function, module,
+            )],
+        }
+    }
+}
+
+/// a command line run through a shell, or a list of commands run one after another without one,
+/// entries of the list that name another script run that script
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ScriptCommand {
+    Line(String),
+    Sequence(Vec<String>),
+}
+
+impl ScriptCommand {
+    pub fn describe(&self) -> String {
+        match self {
+            ScriptCommand::Line(line) => line.clone(),
+            ScriptCommand::Sequence(commands) => commands.join(" && "),
+        }
+    }
+}
+
+/// table form of a script, `test = { cmd = "pytest", cwd = "tests", env = { .. }, help = "..", shell = "sh" }`
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ScriptTable {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmd: Option<ScriptCommand>,
+    /// working directory, relative to the project root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub help: Option<String>,
+    /// shell for a string `cmd`: sh, bash, cmd, powershell, pwsh or none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    /// scripts to run first, each at most once per `ppm run`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends: Vec<String>,
+    /// run the entries of a list `cmd` at the same time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel: Option<bool>,
+    // tables have to be serialized after plain values
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+}
+
+/// An entry of [scripts]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Script {
+    Simple(ScriptCommand),
+    Table(ScriptTable),
+}
+
+impl Script {
+    /// the script's options, with the plain forms as a table that only has `cmd`
+    pub fn table(&self) -> ScriptTable {
+        match self {
+            Script::Simple(cmd) => ScriptTable { cmd: Some(cmd.clone()), ..ScriptTable::default() },
+            Script::Table(table) => table.clone(),
+        }
+    }
+
+    /// `help` if there is one, otherwise the command
+    pub fn describe(&self) -> String {
+        let table = self.table();
+        match (table.help, table.cmd) {
+            (Some(help), _) => help,
+            (None, Some(cmd)) => cmd.describe(),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+/// `[policy.licenses]`, licenses are matched against SPDX ids and trove classifier names
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct LicensePolicy {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Policy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub licenses: Option<LicensePolicy>,
+}
+
+impl Policy {
+    fn is_empty(&self) -> bool {
+        self.licenses.is_none()
+    }
+}
+
+/// `[watch]`, which files `--watch` looks at besides what .gitignore leaves out
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct WatchConfig {
+    /// globs of the files to watch, everything when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// milliseconds to wait for changes to settle before restarting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debounce: Option<u64>,
+    /// milliseconds between looks at the files when file events are unavailable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll_interval: Option<u64>,
+}
+
+impl WatchConfig {
+    fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty() && self.debounce.is_none() && self.poll_interval.is_none()
+    }
+}
+
+/// `[profiles.<name>]`, settings that replace or extend the base ones while the profile is active
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub main_script: Option<String>,
+    /// `[groups.<name>]` whose packages are added to [packages]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<EntryPoint>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty", serialize_with = "toml::ser::tables_last")]
+    pub scripts: HashMap<String, Script>,
+}
+
+/// the profile picked with `--profile` or `PPM_PROFILE`
+pub fn active_profile() -> Option<String> {
+    std::env::var("PPM_PROFILE").ok().filter(|p| !p.is_empty())
+}
+
+/// normalized name of a [packages] entry, which may carry extras like `uvicorn[standard]`
+pub fn package_key(name: &str) -> String {
+    Requirement::parse(name).map(|r| r.key()).unwrap_or_else(|| normalize_name(name))
+}
+
+/// split a [packages] value like `306; sys_platform == "win32"` into version and marker
+pub fn split_marker(value: &str) -> (String, Option<String>) {
+    match value.split_once(';') {
+        Some((version, marker)) => (version.trim().to_string(), Some(marker.trim().to_string())),
+        None => (value.trim().to_string(), None),
+    }
+}
+
+fn to_requirements(packages: &HashMap<String, String>) -> Vec<Requirement> {
+    let mut reqs: Vec<Requirement> = packages
+        .iter()
+        .map(|(name, value)| {
+            let (version, marker) = split_marker(value);
+            let mut req = Requirement::parse(name).unwrap_or_else(|| Requirement {
+                name: name.clone(),
+                extras: vec![],
+                specifier: String::new(),
+                marker: None,
+            });
+            req.specifier = if version.is_empty() { String::new() } else { format!("=={}", version) };
+            req.marker = marker;
+            req
+        })
+        .collect();
+    reqs.sort_by_key(|r| r.key());
+    reqs
+}
+
+/// append the requirements that aren't already listed, a group may repeat a package exactly
+fn push_new(reqs: &mut Vec<Requirement>, more: Vec<Requirement>) {
+    for req in more {
+        if !reqs.contains(&req) {
+            reqs.push(req);
         }
     }
 }
@@ -26,27 +282,273 @@ impl Project {
 pub struct Config {
     pub project: Project,
     pub packages: HashMap<String, String>,
-    pub scripts: HashMap<String, String>,
+    #[serde(serialize_with = "toml::ser::tables_last")]
+    pub scripts: HashMap<String, Script>,
+    /// `[env]`, variables set for scripts, `ppm start` and pip
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// `[groups.<name>]`, extra packages like dev tools that are not part of [packages]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub groups: HashMap<String, HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Policy::is_empty")]
+    pub policy: Policy,
+    #[serde(default, skip_serializing_if = "WatchConfig::is_empty")]
+    pub watch: WatchConfig,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Profile>,
+    /// the active profile, never written back
+    #[serde(skip)]
+    pub profile: Option<String>,
 }
 
 impl Config {
-    pub fn new(project: Project, packages: HashMap<String, String>, scripts: HashMap<String, String>) -> Config {
+    pub fn new(project: Project, packages: HashMap<String, String>, scripts: HashMap<String, Script>) -> Config {
         Config {
             project,
             packages,
             scripts,
+            env: HashMap::new(),
+            groups: HashMap::new(),
+            policy: Policy::default(),
+            watch: WatchConfig::default(),
+            profiles: HashMap::new(),
+            profile: None,
+        }
+    }
+
+    /// the top-level requirements declared in [packages]
+    pub fn requirements(&self) -> Vec<Requirement> {
+        to_requirements(&self.packages)
+    }
+
+    /// what gets installed, [packages] and the groups of the active profile
+    pub fn install_requirements(&self) -> Vec<Requirement> {
+        let mut reqs = self.requirements();
+        for group in self.active_profile().map(|p| p.groups.clone()).unwrap_or_default() {
+            push_new(&mut reqs, self.group_requirements(&group).unwrap_or_default());
+        }
+        reqs
+    }
+
+    /// what ppm.lock is resolved from, [packages] and every `[groups.<name>]` table,
+    /// so the lock is the same whichever profile is active
+    pub fn lock_requirements(&self) -> Vec<Requirement> {
+        let mut reqs = self.requirements();
+        let mut groups: Vec<&String> = self.groups.keys().collect();
+        groups.sort();
+        for group in groups {
+            push_new(&mut reqs, self.group_requirements(group).unwrap_or_default());
+        }
+        reqs
+    }
+
+    pub fn active_profile(&self) -> Option<&Profile> {
+        self.profile.as_ref().and_then(|name| self.profiles.get(name))
+    }
+
+    /// a script, as the active profile defines it if it does
+    pub fn script(&self, name: &str) -> Option<&Script> {
+        self.active_profile().and_then(|p| p.scripts.get(name)).or_else(|| self.scripts.get(name))
+    }
+
+    /// every script with the active profile's definitions in place
+    pub fn effective_scripts(&self) -> BTreeMap<&String, &Script> {
+        let mut scripts: BTreeMap<&String, &Script> = self.scripts.iter().collect();
+        if let Some(profile) = self.active_profile() {
+            scripts.extend(profile.scripts.iter());
         }
+        scripts
+    }
+
+    /// what `ppm start` runs, the target being the first of the profile's entry target,
+    /// the profile's `main_script`, `[project.entry]`'s target and `main_script`
+    pub fn entry(&self) -> EntryPoint {
+        let profile = self.active_profile();
+        let base = self.project.entry.clone().unwrap_or_default();
+        let mut entry = profile.and_then(|p| p.entry.clone()).unwrap_or_else(|| base.clone());
+        entry.target = entry.target
+            .or_else(|| profile.and_then(|p| p.main_script.clone()))
+            .or(base.target)
+            .or_else(|| Some(self.project.main_script.clone()).filter(|m| !m.is_empty()));
+        entry
+    }
+
+    /// the requirements of a `[groups.<name>]` table
+    pub fn group_requirements(&self, group: &str) -> Option<Vec<Requirement>> {
+        self.groups.get(group).map(to_requirements)
+    }
+
+    /// the [packages] entry for a package, whatever its spelling or extras
+    pub fn find_package(&self, name: &str) -> Option<String> {
+        let key = package_key(name);
+        self.packages.keys().find(|k| package_key(k) == key).cloned()
+    }
+
+    /// pin a package to `version`, keeping the extras and marker of the requirement
+    pub fn set_package(&mut self, req: &Requirement, version: &str) {
+        if let Some(old) = self.find_package(&req.name) {
+            self.packages.remove(&old);
+        }
+        let name = if req.extras.is_empty() { req.name.clone() } else { format!("{}[{}]", req.name, req.extras.join(",")) };
+        let value = match &req.marker {
+            Some(marker) => format!("{}; {}", version, marker),
+            None => version.to_string(),
+        };
+        self.packages.insert(name, value);
     }
 
     pub fn write_to_file(&self, path: &str) -> Result<(), Error> {
         let toml_string = toml::to_string(&self).unwrap();
-        fs::write(path, toml_string)
+        write_atomic(path, &toml_string)
     }
 
     pub fn load_from_file(path: &str) -> Result<Config, Error> {
         let toml_string = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&toml_string)?;
+        let mut config: Config = toml::from_str(&toml_string)?;
+        config.select_profile(active_profile())?;
         Ok(config)
     }
+
+    /// make `profile` the active one, it has to exist and only use groups that do
+    pub fn select_profile(&mut self, profile: Option<String>) -> Result<(), Error> {
+        if let Some(name) = &profile {
+            // a profile may be nothing more than a .env.<name> file
+            let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+            match self.profiles.get(name) {
+                Some(profile) => {
+                    if let Some(group) = profile.groups.iter().find(|g| !self.groups.contains_key(*g)) {
+                        return Err(invalid(format!("Profile '{}' uses group '{}', which has no [groups.{}] table", name, group, group)));
+                    }
+                },
+                None if Path::new(&format!(".env.{}", name)).exists() => {},
+                None => return Err(invalid(format!("Unknown profile '{}', there is no [profiles.{}] in project.toml", name, name))),
+            }
+        }
+        self.profile = profile;
+        Ok(())
+    }
     
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+[project]
+name = "p"
+version = "0.1"
+description = ""
+main_script = "src/main.py"
+
+[project.entry]
+args = ["--verbose"]
+env = { LOG = "info" }
+
+[packages]
+requests = "2.31.0"
+
+[scripts]
+test = "pytest"
+
+[groups.dev]
+pytest = "8.1"
+
+[groups.docs]
+mkdocs = "1.5"
+
+[profiles.dev]
+groups = ["dev"]
+main_script = "myapp.dev:serve"
+scripts = { test = "pytest -x" }
+
+[profiles.worker.entry]
+target = "myapp.worker"
+cwd = "services"
+"#;
+
+    fn config(profile: Option<&str>) -> Config {
+        let mut config: Config = toml::from_str(MANIFEST).unwrap();
+        config.select_profile(profile.map(|p| p.to_string())).unwrap();
+        config
+    }
+
+    fn keys(reqs: &[Requirement]) -> Vec<String> {
+        reqs.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_entry_targets() {
+        assert_eq!(EntryTarget::parse("src/main.py"), EntryTarget::Path("src/main.py".to_string()));
+        assert_eq!(EntryTarget::parse("main.py"), EntryTarget::Path("main.py".to_string()));
+        assert_eq!(EntryTarget::parse("bin/run-app"), EntryTarget::Path("bin/run-app".to_string()));
+        assert_eq!(EntryTarget::parse("C:\\app\\main.py"), EntryTarget::Path("C:\\app\\main.py".to_string()));
+        assert_eq!(EntryTarget::parse("myapp"), EntryTarget::Module("myapp".to_string()));
+        assert_eq!(EntryTarget::parse("myapp.__main__"), EntryTarget::Module("myapp.__main__".to_string()));
+        assert_eq!(EntryTarget::parse("myapp.cli:main"), EntryTarget::Callable("myapp.cli".to_string(), "main".to_string()));
+        assert_eq!(EntryTarget::parse("myapp:App.run"), EntryTarget::Callable("myapp".to_string(), "App.run".to_string()));
+        assert_eq!(EntryTarget::parse("my-app:main"), EntryTarget::Path("my-app:main".to_string()));
+    }
+
+    #[test]
+    fn builds_python_arguments_for_each_target() {
+        assert_eq!(EntryTarget::parse("main.py").python_args(), vec!["main.py"]);
+        assert_eq!(EntryTarget::parse("myapp").python_args(), vec!["-m", "myapp"]);
+        let args = EntryTarget::parse("myapp.cli:App.run").python_args();
+        assert_eq!(args[0], "-c");
+        assert!(args[1].contains("import_module('myapp.cli')"));
+        assert!(args[1].contains("'App.run'.split('.')"));
+        assert!(args[1].ends_with("sys.exit(obj())"));
+    }
+
+    #[test]
+    fn picks_the_entry_of_the_active_profile() {
+        let entry = config(None).entry();
+        assert_eq!(entry.target(), Ok(EntryTarget::Path("src/main.py".to_string())));
+        assert_eq!(entry.args, vec!["--verbose"]);
+        assert_eq!(entry.env["LOG"], "info");
+
+        // a profile's main_script replaces the target and keeps the base entry's options
+        let entry = config(Some("dev")).entry();
+        assert_eq!(entry.target(), Ok(EntryTarget::Callable("myapp.dev".to_string(), "serve".to_string())));
+        assert_eq!(entry.args, vec!["--verbose"]);
+
+        // a profile's entry replaces the base one
+        let entry = config(Some("worker")).entry();
+        assert_eq!(entry.target(), Ok(EntryTarget::Module("myapp.worker".to_string())));
+        assert!(entry.args.is_empty());
+        assert_eq!(entry.cwd.as_deref(), Some("services"));
+
+        let mut conf = config(None);
+        conf.project.main_script = String::new();
+        conf.project.entry = None;
+        assert!(conf.entry().target().is_err());
+    }
+
+    #[test]
+    fn profiles_add_groups_and_scripts() {
+        let base = config(None);
+        assert_eq!(keys(&base.install_requirements()), vec!["requests==2.31.0"]);
+        assert_eq!(base.script("test").unwrap().describe(), "pytest");
+
+        let dev = config(Some("dev"));
+        assert_eq!(keys(&dev.install_requirements()), vec!["requests==2.31.0", "pytest==8.1"]);
+        assert_eq!(dev.script("test").unwrap().describe(), "pytest -x");
+        // the lock covers every group, whichever profile is active
+        assert_eq!(keys(&dev.lock_requirements()), keys(&base.lock_requirements()));
+        assert_eq!(keys(&base.lock_requirements()), vec!["requests==2.31.0", "pytest==8.1", "mkdocs==1.5"]);
+    }
+
+    #[test]
+    fn rejects_unknown_profiles_and_groups() {
+        let mut conf: Config = toml::from_str(MANIFEST).unwrap();
+        let err = conf.select_profile(Some("staging".to_string())).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown profile 'staging', there is no [profiles.staging] in project.toml");
+        assert!(conf.profile.is_none());
+
+        conf.profiles.get_mut("dev").unwrap().groups.push("lint".to_string());
+        let err = conf.select_profile(Some("dev".to_string())).unwrap_err();
+        assert_eq!(err.to_string(), "Profile 'dev' uses group 'lint', which has no [groups.lint] table");
+    }
+}
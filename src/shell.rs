@@ -0,0 +1,258 @@
+use clap::Args;
+use std::{ffi::OsString, fs, path::{Path, PathBuf}, process::Command};
+use crate::environment::{apply_project_env, project_env};
+use crate::process::run_foreground;
+use crate::settings::Config;
+use crate::utils::*;
+
+/// The kinds of shell `ppm shell` knows how to activate
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ShellKind {
+    /// bash, zsh, sh and the like, by program name
+    Posix(String),
+    Fish,
+    /// `powershell` or `pwsh`
+    PowerShell(String),
+    Cmd,
+}
+
+impl ShellKind {
+    fn from_name(name: &str) -> ShellKind {
+        let name = Path::new(name).file_stem().map(|s| s.to_string_lossy().to_lowercase()).unwrap_or_default();
+        match name.as_str() {
+            "fish" => ShellKind::Fish,
+            "powershell" | "pwsh" => ShellKind::PowerShell(name),
+            "cmd" => ShellKind::Cmd,
+            "" => ShellKind::Posix("sh".to_string()),
+            _ => ShellKind::Posix(name),
+        }
+    }
+
+    /// the shell ppm was started from
+    fn detect() -> ShellKind {
+        if cfg!(target_os = "windows") {
+            // cmd sets PROMPT, PowerShell doesn't
+            return if std::env::var_os("PROMPT").is_some() { ShellKind::Cmd } else { ShellKind::PowerShell("powershell".to_string()) };
+        }
+        match std::env::var("SHELL") {
+            Ok(shell) if !shell.is_empty() => ShellKind::from_name(&shell),
+            _ => ShellKind::Posix("sh".to_string()),
+        }
+    }
+
+    fn program(&self) -> String {
+        match self {
+            ShellKind::Posix(name) | ShellKind::PowerShell(name) => name.clone(),
+            ShellKind::Fish => "fish".to_string(),
+            ShellKind::Cmd => "cmd".to_string(),
+        }
+    }
+}
+
+fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn powershell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// the variables to set (or unset, for `None`) to activate the project: the same as `ppm run`
+/// sets, plus the prompt name
+fn activation_vars(conf: &Config) -> Vec<(String, Option<OsString>)> {
+    let mut cmd = Command::new("");
+    apply_project_env(&mut cmd);
+    activate_venv(&mut cmd);
+    cmd.env("VIRTUAL_ENV_PROMPT", &conf.project.name);
+    cmd.env("PPM_SHELL", "1");
+    cmd.get_envs()
+        .map(|(key, value)| (key.to_string_lossy().to_string(), value.map(|v| v.to_os_string())))
+        .collect()
+}
+
+/// code that activates the project in `shell`, for it to evaluate
+fn activation_script(shell: &ShellKind, conf: &Config) -> String {
+    let name = &conf.project.name;
+    let mut out = String::new();
+    for (key, value) in activation_vars(conf) {
+        let value = value.map(|v| v.to_string_lossy().to_string());
+        let line = match (shell, value) {
+            (ShellKind::Posix(_), Some(value)) => format!("export {}={}", key, posix_quote(&value)),
+            (ShellKind::Posix(_), None) => format!("unset {}", key),
+            // fish keeps PATH as a list
+            (ShellKind::Fish, Some(value)) if key == "PATH" => {
+                let paths: Vec<String> = std::env::split_paths(&value).map(|p| fish_quote(&p.to_string_lossy())).collect();
+                format!("set -gx PATH {}", paths.join(" "))
+            },
+            (ShellKind::Fish, Some(value)) => format!("set -gx {} {}", key, fish_quote(&value)),
+            (ShellKind::Fish, None) => format!("set -e {}", key),
+            (ShellKind::PowerShell(_), Some(value)) => format!("$env:{} = {}", key, powershell_quote(&value)),
+            (ShellKind::PowerShell(_), None) => format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", key),
+            (ShellKind::Cmd, Some(value)) => format!("set \"{}={}\"", key, value),
+            (ShellKind::Cmd, None) => format!("set {}=", key),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+
+    let prompt = match shell {
+        ShellKind::Posix(_) => format!("PS1={}\"${{PS1-}}\"\n", posix_quote(&format!("({}) ", name))),
+        ShellKind::Fish => format!(
+            "functions -q _ppm_fish_prompt; or functions -c fish_prompt _ppm_fish_prompt\nfunction fish_prompt; echo -n {}; _ppm_fish_prompt; end\n",
+            fish_quote(&format!("({}) ", name)),
+        ),
+        ShellKind::PowerShell(_) => format!(
+            "if (-not (Test-Path Function:_ppm_prompt)) {{ Copy-Item Function:prompt Function:_ppm_prompt }}\nfunction global:prompt {{ {} + (_ppm_prompt) }}\n",
+            powershell_quote(&format!("({}) ", name)),
+        ),
+        ShellKind::Cmd => format!("set \"PROMPT=({}) $P$G\"\n", name),
+    };
+    out.push_str(&prompt);
+    out
+}
+
+/// a directory for the startup files of a shell, removed when dropped
+struct StartupDir(PathBuf);
+
+impl StartupDir {
+    fn new() -> Result<StartupDir, String> {
+        let dir = std::env::temp_dir().join(format!("ppm-shell-{}", std::process::id()));
+        fs::create_dir_all(&dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        Ok(StartupDir(dir))
+    }
+
+    fn write(&self, name: &str, contents: &str) -> Result<PathBuf, String> {
+        let path = self.0.join(name);
+        fs::write(&path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+}
+
+impl Drop for StartupDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// the shell command, activating the project after the user's own startup files ran
+fn shell_command(shell: &ShellKind, script: &str, dir: &StartupDir) -> Result<Command, String> {
+    let mut cmd = Command::new(shell.program());
+    match shell {
+        ShellKind::Posix(name) if name == "bash" => {
+            let rc = dir.write("bashrc", &format!("[ -f ~/.bashrc ] && . ~/.bashrc\n{}", script))?;
+            cmd.arg("--rcfile").arg(rc).arg("-i");
+        },
+        ShellKind::Posix(name) if name == "zsh" => {
+            // zsh reads its startup files from ZDOTDIR, point it at ours and ours back at the user's
+            let home = std::env::var("ZDOTDIR").or_else(|_| std::env::var("HOME")).unwrap_or_default();
+            let restore = format!("ZDOTDIR={}\n", posix_quote(&home));
+            dir.write(".zshenv", &format!("{}[ -f \"$ZDOTDIR/.zshenv\" ] && . \"$ZDOTDIR/.zshenv\"\nZDOTDIR={}\n", restore, posix_quote(&dir.0.to_string_lossy())))?;
+            dir.write(".zshrc", &format!("{}[ -f \"$ZDOTDIR/.zshrc\" ] && . \"$ZDOTDIR/.zshrc\"\n{}", restore, script))?;
+            cmd.env("ZDOTDIR", &dir.0).arg("-i");
+        },
+        ShellKind::Posix(_) => {
+            // plain sh has no rc file to hook into, ENV is read by interactive shells
+            let rc = dir.write("shrc", script)?;
+            cmd.env("ENV", rc).arg("-i");
+        },
+        ShellKind::Fish => {
+            cmd.arg("--init-command").arg(script);
+        },
+        ShellKind::PowerShell(_) => {
+            cmd.arg("-NoExit").arg("-Command").arg(script.replace('\n', "; "));
+        },
+        // the prompt is an environment variable, set with the others
+        ShellKind::Cmd => {
+            cmd.arg("/K");
+        },
+    }
+    Ok(cmd)
+}
+
+
+#[derive(Args, Debug)]
+pub struct OpenShell {
+
+    /// Print the activation code for `eval` instead of starting a shell
+    #[clap(long = "print", takes_value = false)]
+    pub print: bool,
+
+    /// Shell to use: bash, zsh, fish, sh, powershell, pwsh or cmd (detected by default)
+    #[clap(long = "shell")]
+    pub shell: Option<String>,
+
+}
+
+impl OpenShell {
+    pub fn run(&self) {
+        if !Path::new("project.toml").exists() {
+            eprint("Could not find project.toml".to_owned());
+            return;
+        }
+
+        let conf = match Config::load_from_file("project.toml") {
+            Ok(conf) => conf,
+            Err(e) => {
+                eprint(e.to_string());
+                return;
+            }
+        };
+        if let Err(e) = project_env() {
+            eprint(e.clone());
+            std::process::exit(1);
+        }
+
+        let shell = self.shell.as_deref().map(ShellKind::from_name).unwrap_or_else(ShellKind::detect);
+        let script = activation_script(&shell, &conf);
+        if self.print {
+            print!("{}", script);
+            return;
+        }
+
+        if std::env::var_os("PPM_SHELL").is_some() {
+            wprint("Already inside a ppm shell, starting another one".to_owned());
+        }
+        if !check_venv_dir_exists() {
+            wprint("Virtual Environment Not Found, the shell will use the system python".to_owned());
+        }
+
+        let dir = match StartupDir::new() {
+            Ok(dir) => dir,
+            Err(e) => {
+                eprint(e);
+                std::process::exit(1);
+            }
+        };
+        let mut cmd = match shell_command(&shell, &script, &dir) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                eprint(e);
+                std::process::exit(1);
+            }
+        };
+        for (key, value) in activation_vars(&conf) {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            };
+        }
+        if let ShellKind::Cmd = shell {
+            cmd.env("PROMPT", format!("({}) $P$G", conf.project.name));
+        }
+
+        iprint(format!("Starting {} for {}, exit it to leave", shell.program(), conf.project.name));
+        let code = match run_foreground(&mut cmd) {
+            Ok(code) => code,
+            Err(e) => {
+                eprint(format!("Could not start {}: {}", shell.program(), e));
+                1
+            }
+        };
+        drop(dir);
+        std::process::exit(code);
+    }
+}
@@ -0,0 +1,640 @@
+use globset::{Glob, GlobMatcher};
+use serde::Deserialize;
+use std::{collections::{BTreeMap, HashMap}, fs, io::IsTerminal, path::{Component, Path, PathBuf}, process::Command, time::SystemTime};
+use crate::utils::*;
+
+/// the file of a template directory that describes it, not copied into projects
+const MANIFEST: &str = "template.toml";
+
+/// the templates `ppm new --template` has without looking anywhere
+pub const BUILTIN_TEMPLATES: [&str; 4] = ["app", "lib", "cli", "flask"];
+
+const APP_MAIN: &str = "
+def main():
+    print('Hello From PPM!')
+
+if __name__ == '__main__':
+    main()
+";
+
+const LIB_INIT: &str = "\"\"\"{{ description }}\"\"\"
+
+__version__ = '{{ version }}'
+";
+
+const LIB_TEST: &str = "import unittest
+
+import {{ package }}
+
+
+class Test{{ class }}(unittest.TestCase):
+    def test_version(self):
+        self.assertEqual({{ package }}.__version__, '{{ version }}')
+
+
+if __name__ == '__main__':
+    unittest.main()
+";
+
+const CLI_MAIN: &str = "import argparse
+
+from {{ package }} import __version__
+
+
+def main(argv=None):
+    parser = argparse.ArgumentParser(prog='{{ name }}', description='{{ description }}')
+    parser.add_argument('--version', action='version', version=__version__)
+    parser.add_argument('name', nargs='?', default='world')
+    args = parser.parse_args(argv)
+    print(f'Hello, {args.name}!')
+    return 0
+";
+
+const CLI_DUNDER_MAIN: &str = "import sys
+
+from {{ package }}.cli import main
+
+sys.exit(main())
+";
+
+const FLASK_INIT: &str = "from flask import Flask
+
+
+def create_app():
+    app = Flask(__name__)
+
+    @app.get('/')
+    def index():
+        return {'name': '{{ name }}', 'version': '{{ version }}'}
+
+    return app
+";
+
+const FLASK_APP: &str = "import os
+
+from {{ package }} import create_app
+
+app = create_app()
+
+
+def main():
+    app.run(debug=True, port=int(os.environ.get('PORT', 5000)))
+";
+
+const FLASK_TEST: &str = "import unittest
+
+from {{ package }} import create_app
+
+
+class TestIndex(unittest.TestCase):
+    def test_index(self):
+        client = create_app().test_client()
+        self.assertEqual(client.get('/').status_code, 200)
+
+
+if __name__ == '__main__':
+    unittest.main()
+";
+
+/// the import name of a project: `My-App` becomes `my_app`
+pub fn package_name(project: &str) -> String {
+    let mut name: String = project
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// A file a template creates, relative to the project root
+pub struct TemplateFile {
+    pub path: String,
+    pub contents: Vec<u8>,
+}
+
+/// `[[variables]]` of template.toml
+#[derive(Deserialize, Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    /// may use the variables declared before it
+    #[serde(default)]
+    pub default: String,
+    /// asked on the terminal when there is one
+    pub prompt: Option<String>,
+}
+
+/// template.toml, everything is optional
+#[derive(Deserialize, Debug, Default)]
+struct Manifest {
+    main_script: Option<String>,
+    #[serde(default)]
+    packages: Vec<String>,
+    /// commands run in the new project once it's set up
+    #[serde(default)]
+    post_generate: Vec<String>,
+    /// globs of files copied without filling anything in
+    #[serde(default)]
+    copy: Vec<String>,
+    #[serde(default)]
+    variables: Vec<Variable>,
+    /// glob to the condition under which matching files are created
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+    #[serde(default)]
+    scripts: HashMap<String, String>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+}
+
+fn glob(pattern: &str) -> Result<GlobMatcher, String> {
+    let pattern = pattern.trim_start_matches("./");
+    Glob::new(pattern)
+        .map(|g| g.compile_matcher())
+        .map_err(|e| format!("Invalid pattern '{}' in {}: {}", pattern, MANIFEST, e))
+}
+
+/// What a new project starts with
+#[derive(Default)]
+pub struct Template {
+    pub files: Vec<TemplateFile>,
+    pub main_script: String,
+    pub scripts: Vec<(String, String)>,
+    pub env: Vec<(String, String)>,
+    /// packages to add once the venv is there
+    pub packages: Vec<String>,
+    pub variables: Vec<Variable>,
+    /// files only created when a condition holds
+    conditions: Vec<(GlobMatcher, String)>,
+    /// files copied as they are
+    copy: Vec<GlobMatcher>,
+    pub post_generate: Vec<String>,
+    /// fetched from a git url, its commands aren't run without asking
+    pub remote: bool,
+}
+
+impl Template {
+    fn file(&mut self, path: &str, contents: &str) {
+        self.files.push(TemplateFile { path: path.to_string(), contents: contents.as_bytes().to_vec() });
+    }
+
+    fn script(&mut self, name: &str, cmd: &str) {
+        self.scripts.push((name.to_string(), cmd.to_string()));
+    }
+
+    /// a built-in template by name
+    pub fn builtin(name: &str) -> Option<Template> {
+        let mut template = Template::default();
+        let src_layout = |template: &mut Template| {
+            template.env.push(("PYTHONPATH".to_string(), "src".to_string()));
+            template.script("test", "python -m unittest discover -s tests");
+        };
+        match name {
+            "app" => {
+                template.file("src/main.py", APP_MAIN);
+                template.main_script = "./src/main.py".to_string();
+            },
+            "lib" => {
+                template.file("src/{{ package }}/__init__.py", LIB_INIT);
+                template.file("tests/__init__.py", "");
+                template.file("tests/test_{{ package }}.py", LIB_TEST);
+                src_layout(&mut template);
+            },
+            "cli" => {
+                template.file("src/{{ package }}/__init__.py", LIB_INIT);
+                template.file("src/{{ package }}/__main__.py", CLI_DUNDER_MAIN);
+                template.file("src/{{ package }}/cli.py", CLI_MAIN);
+                template.file("tests/__init__.py", "");
+                template.file("tests/test_{{ package }}.py", LIB_TEST);
+                template.main_script = "{{ package }}.cli:main".to_string();
+                src_layout(&mut template);
+            },
+            "flask" => {
+                template.file("src/{{ package }}/__init__.py", FLASK_INIT);
+                template.file("src/{{ package }}/app.py", FLASK_APP);
+                template.file("tests/__init__.py", "");
+                template.file("tests/test_{{ package }}.py", FLASK_TEST);
+                template.main_script = "{{ package }}.app:main".to_string();
+                template.packages.push("flask".to_string());
+                src_layout(&mut template);
+            },
+            _ => return None,
+        }
+        Some(template)
+    }
+
+    /// every file below a template directory, `.git` left out
+    fn from_dir(dir: &Path) -> Result<Template, String> {
+        let mut template = Template::default();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            let entries = fs::read_dir(&current).map_err(|e| format!("Could not read {}: {}", current.display(), e))?;
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if entry.file_name() == ".git" {
+                    continue;
+                }
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let relative = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+                if relative == MANIFEST {
+                    continue;
+                }
+                let contents = fs::read(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+                template.files.push(TemplateFile { path: relative, contents });
+            }
+        }
+        template.files.sort_by(|a, b| a.path.cmp(&b.path));
+        template.main_script = ["src/main.py", "main.py", "app.py"]
+            .iter()
+            .find(|p| template.files.iter().any(|f| f.path == **p))
+            .map(|p| format!("./{}", p))
+            .unwrap_or_default();
+
+        let manifest_path = dir.join(MANIFEST);
+        if manifest_path.exists() {
+            let contents = fs::read_to_string(&manifest_path).map_err(|e| format!("Could not read {}: {}", manifest_path.display(), e))?;
+            let manifest: Manifest = toml::from_str(&contents).map_err(|e| format!("Invalid {}: {}", MANIFEST, e))?;
+            if let Some(main_script) = manifest.main_script {
+                template.main_script = main_script;
+            }
+            template.packages = manifest.packages;
+            template.post_generate = manifest.post_generate;
+            template.variables = manifest.variables;
+            template.scripts = manifest.scripts.into_iter().collect();
+            template.env = manifest.env.into_iter().collect();
+            for pattern in manifest.copy.iter() {
+                template.copy.push(glob(pattern)?);
+            }
+            for (pattern, condition) in manifest.files.into_iter() {
+                template.conditions.push((glob(&pattern)?, condition));
+            }
+        }
+        Ok(template)
+    }
+
+    /// a built-in template, a directory, a git repository or a template saved under
+    /// `<config dir>/ppm/templates`
+    pub fn load(spec: &str) -> Result<Template, String> {
+        if let Some(template) = Template::builtin(spec) {
+            return Ok(template);
+        }
+        if is_git_url(spec) {
+            let checkout = TempCheckout::clone(spec)?;
+            let mut template = Template::from_dir(&checkout.0)?;
+            template.remote = true;
+            return Ok(template);
+        }
+        if Path::new(spec).is_dir() {
+            return Template::from_dir(Path::new(spec));
+        }
+        match user_templates_dir().map(|dir| dir.join(spec)) {
+            Some(dir) if dir.is_dir() => Template::from_dir(&dir),
+            _ => Err(format!(
+                "Unknown template '{}', use one of {}, a directory or a git url",
+                spec,
+                BUILTIN_TEMPLATES.join(", "),
+            )),
+        }
+    }
+
+    /// work out the template's own variables: given with `--var`, asked for, or their default
+    pub fn resolve_variables(&self, context: &mut Context, given: &HashMap<String, String>) {
+        let interactive = std::io::stdin().is_terminal();
+        for var in self.variables.iter() {
+            let default = render(&var.default, context);
+            let value = match (given.get(&var.name), &var.prompt) {
+                (Some(value), _) => value.clone(),
+                (None, Some(prompt)) if interactive => ask(prompt, &default),
+                _ => default,
+            };
+            context.insert(var.name.clone(), value);
+        }
+    }
+
+    /// fill in the variables, in paths as well as contents, leaving out files whose condition doesn't hold
+    pub fn render(&mut self, context: &Context) -> Result<(), String> {
+        let mut files = vec![];
+        for mut file in std::mem::take(&mut self.files) {
+            let mut keep = true;
+            for (matcher, condition) in self.conditions.iter() {
+                if matcher.is_match(&file.path) && !condition_holds(condition, context)? {
+                    keep = false;
+                }
+            }
+            if !keep {
+                continue;
+            }
+            let verbatim = self.copy.iter().any(|m| m.is_match(&file.path));
+            file.path = render(&file.path, context);
+            check_path(&file.path)?;
+            if !verbatim {
+                if let Ok(text) = std::str::from_utf8(&file.contents) {
+                    file.contents = render(text, context).into_bytes();
+                }
+            }
+            files.push(file);
+        }
+        self.files = files;
+        self.main_script = render(&self.main_script, context);
+        for (_, cmd) in self.scripts.iter_mut() {
+            *cmd = render(cmd, context);
+        }
+        for (_, value) in self.env.iter_mut() {
+            *value = render(value, context);
+        }
+        for cmd in self.post_generate.iter_mut() {
+            *cmd = render(cmd, context);
+        }
+        Ok(())
+    }
+}
+
+/// the values `{{ name }}` placeholders are filled with
+pub type Context = BTreeMap<String, String>;
+
+/// the variables every template has
+pub fn base_context(name: &str, version: &str, description: &str, author: &str) -> Context {
+    let package = package_name(name);
+    let class: String = package.split('_').map(|part| {
+        let mut chars = part.chars();
+        chars.next().map(|c| c.to_ascii_uppercase().to_string() + chars.as_str()).unwrap_or_default()
+    }).collect();
+    let mut context = Context::new();
+    context.insert("name".to_string(), name.to_string());
+    context.insert("package".to_string(), package);
+    context.insert("class".to_string(), class);
+    context.insert("version".to_string(), version.to_string());
+    context.insert("description".to_string(), description.to_string());
+    context.insert("author".to_string(), author.to_string());
+    context.insert("year".to_string(), current_year().to_string());
+    context
+}
+
+/// the user's name from git, or their login
+pub fn default_author() -> String {
+    let git = Command::new("git").args(["config", "user.name"]).output().ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .filter(|name| !name.is_empty());
+    git.or_else(|| std::env::var("USER").ok())
+        .or_else(|| std::env::var("USERNAME").ok())
+        .unwrap_or_default()
+}
+
+fn current_year() -> i64 {
+    let days = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs() as i64 / 86400).unwrap_or(0);
+    // civil calendar from days since 1970-01-01, see howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let month = (5 * doy + 2) / 153;
+    let year = yoe + era * 400;
+    if month >= 10 { year + 1 } else { year }
+}
+
+/// replace `{{ var }}` with its value, placeholders of unknown variables are left as they are
+pub fn render(text: &str, context: &Context) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else { break };
+        let name = rest[start + 2..start + len].trim();
+        out.push_str(&rest[..start]);
+        match context.get(name) {
+            Some(value) => out.push_str(value),
+            None => out.push_str(&rest[start..start + len + 2]),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// a rendered path must stay inside the project
+fn check_path(path: &str) -> Result<(), String> {
+    let inside = Path::new(path).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if path.is_empty() || !inside {
+        return Err(format!("Template file '{}' is not a path inside the project", path));
+    }
+    Ok(())
+}
+
+fn is_truthy(value: &str) -> bool {
+    !matches!(value.trim().to_lowercase().as_str(), "" | "0" | "false" | "no" | "n" | "off")
+}
+
+/// `var`, `!var`, `var == value` or `var != value`
+fn condition_holds(condition: &str, context: &Context) -> Result<bool, String> {
+    let value = |name: &str| -> Result<String, String> {
+        context.get(name.trim()).cloned().ok_or_else(|| format!("Unknown variable '{}' in condition '{}'", name.trim(), condition))
+    };
+    let unquote = |s: &str| s.trim().trim_matches(|c| c == '"' || c == '\'').to_string();
+    if let Some((name, expected)) = condition.split_once("!=") {
+        return Ok(value(name)? != unquote(expected));
+    }
+    if let Some((name, expected)) = condition.split_once("==") {
+        return Ok(value(name)? == unquote(expected));
+    }
+    match condition.trim().strip_prefix('!') {
+        Some(name) => Ok(!is_truthy(&value(name)?)),
+        None => Ok(is_truthy(&value(condition)?)),
+    }
+}
+
+/// `~/.config/ppm/templates` or its equivalent, or `PPM_TEMPLATES_DIR`
+fn user_templates_dir() -> Option<PathBuf> {
+    match std::env::var("PPM_TEMPLATES_DIR") {
+        Ok(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(dirs::config_dir()?.join("ppm").join("templates")),
+    }
+}
+
+fn is_git_url(spec: &str) -> bool {
+    ["https://", "http://", "git@", "ssh://", "git://", "file://"].iter().any(|p| spec.starts_with(p)) || spec.ends_with(".git")
+}
+
+/// a shallow clone of a template repository, removed when dropped
+struct TempCheckout(PathBuf);
+
+impl TempCheckout {
+    fn clone(url: &str) -> Result<TempCheckout, String> {
+        let dir = std::env::temp_dir().join(format!("ppm-template-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        iprint(format!("Cloning template {}", url));
+        let status = Command::new("git")
+            .args(["clone", "--depth", "1", "--quiet", url])
+            .arg(&dir)
+            .status()
+            .map_err(|e| format!("Could not run git: {}", e))?;
+        if !status.success() {
+            return Err(format!("Could not clone template {}", url));
+        }
+        Ok(TempCheckout(dir))
+    }
+}
+
+impl Drop for TempCheckout {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(vars: &[(&str, &str)]) -> Context {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn fills_in_known_variables_only() {
+        let context = context(&[("name", "demo"), ("package", "demo_pkg")]);
+        assert_eq!(render("{{name}} / {{ package }}!", &context), "demo / demo_pkg!");
+        assert_eq!(render("{{ unknown }} and {{name}}", &context), "{{ unknown }} and demo");
+        assert_eq!(render("open {{ name", &context), "open {{ name");
+        assert_eq!(render("no placeholders", &context), "no placeholders");
+    }
+
+    #[test]
+    fn derives_package_and_class_names() {
+        assert_eq!(package_name("My-App.v2"), "my_app_v2");
+        assert_eq!(package_name("3d-tools"), "_3d_tools");
+        let context = base_context("my-web-app", "0.1.0", "A demo", "Ada");
+        assert_eq!(context["package"], "my_web_app");
+        assert_eq!(context["class"], "MyWebApp");
+        assert_eq!(context["author"], "Ada");
+        assert!(context["year"].parse::<i64>().unwrap() >= 2024);
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        let context = context(&[("docker", "yes"), ("ci", "false"), ("db", "postgres"), ("empty", "")]);
+        assert_eq!(condition_holds("docker", &context), Ok(true));
+        assert_eq!(condition_holds(" !ci ", &context), Ok(true));
+        assert_eq!(condition_holds("empty", &context), Ok(false));
+        assert_eq!(condition_holds("db == 'postgres'", &context), Ok(true));
+        assert_eq!(condition_holds("db != \"postgres\"", &context), Ok(false));
+        assert_eq!(condition_holds("db==sqlite", &context), Ok(false));
+        assert_eq!(condition_holds("redis", &context), Err("Unknown variable 'redis' in condition 'redis'".to_string()));
+    }
+
+    #[test]
+    fn keeps_paths_inside_the_project() {
+        assert!(check_path("src/app/__init__.py").is_ok());
+        assert!(check_path("./README.md").is_ok());
+        assert!(check_path("../outside.py").is_err());
+        assert!(check_path("src/../../outside.py").is_err());
+        assert!(check_path("/etc/passwd").is_err());
+        assert!(check_path("").is_err());
+    }
+
+    #[test]
+    fn recognizes_git_urls() {
+        assert!(is_git_url("https://github.com/user/template"));
+        assert!(is_git_url("git@github.com:user/template.git"));
+        assert!(is_git_url("file:///srv/templates/web"));
+        assert!(!is_git_url("./templates/web"));
+        assert!(!is_git_url("flask"));
+    }
+
+    fn template_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ppm-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, contents) in files.iter() {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    const MANIFEST_TOML: &str = r#"
+main_script = "{{ package }}.cli:main"
+post_generate = ["git init", "echo {{ name }}"]
+copy = ["assets/**"]
+
+[[variables]]
+name = "docker"
+default = "no"
+
+[[variables]]
+name = "image"
+default = "{{ package }}:latest"
+
+[files]
+"docker/**" = "docker"
+
+[scripts]
+test = "pytest tests/{{ package }}"
+"#;
+
+    #[test]
+    fn renders_a_template_directory() {
+        let dir = template_dir("render", &[
+            ("template.toml", MANIFEST_TOML),
+            ("src/{{ package }}/__init__.py", "NAME = '{{ name }}'\n"),
+            ("docker/Dockerfile", "FROM {{ image }}\n"),
+            ("assets/raw.txt", "{{ name }} stays\n"),
+            (".git/HEAD", "ref: refs/heads/main\n"),
+        ]);
+        let mut template = Template::from_dir(&dir).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        let paths: Vec<&str> = template.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["assets/raw.txt", "docker/Dockerfile", "src/{{ package }}/__init__.py"]);
+
+        let mut context = base_context("My-App", "0.1.0", "", "");
+        template.resolve_variables(&mut context, &HashMap::new());
+        assert_eq!(context["image"], "my_app:latest");
+        template.render(&context).unwrap();
+
+        let files: Vec<(&str, String)> = template.files.iter().map(|f| (f.path.as_str(), String::from_utf8_lossy(&f.contents).to_string())).collect();
+        assert_eq!(files, vec![
+            ("assets/raw.txt", "{{ name }} stays\n".to_string()),
+            ("src/my_app/__init__.py", "NAME = 'My-App'\n".to_string()),
+        ]);
+        assert_eq!(template.main_script, "my_app.cli:main");
+        assert_eq!(template.scripts, vec![("test".to_string(), "pytest tests/my_app".to_string())]);
+        assert_eq!(template.post_generate, vec!["git init", "echo My-App"]);
+    }
+
+    #[test]
+    fn includes_conditional_files_when_asked() {
+        let dir = template_dir("condition", &[
+            ("template.toml", MANIFEST_TOML),
+            ("docker/Dockerfile", "FROM {{ image }}\n"),
+        ]);
+        let mut template = Template::from_dir(&dir).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        let mut context = base_context("app", "0.1.0", "", "");
+        let given: HashMap<String, String> = [("docker".to_string(), "yes".to_string())].into_iter().collect();
+        template.resolve_variables(&mut context, &given);
+        template.render(&context).unwrap();
+        assert_eq!(template.files.len(), 1);
+        assert_eq!(template.files[0].contents, b"FROM app:latest\n");
+    }
+
+    #[test]
+    fn rejects_files_rendered_outside_the_project() {
+        let dir = template_dir("escape", &[("{{ target }}/x.py", "")]);
+        let mut template = Template::from_dir(&dir).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        let err = template.render(&context(&[("target", "..")])).unwrap_err();
+        assert_eq!(err, "Template file '../x.py' is not a path inside the project");
+    }
+
+    #[test]
+    fn has_the_builtin_templates() {
+        for name in BUILTIN_TEMPLATES {
+            assert!(Template::builtin(name).is_some(), "{}", name);
+        }
+        assert!(Template::builtin("django").is_none());
+    }
+}
@@ -0,0 +1,277 @@
+use std::{fs, path::PathBuf, collections::HashMap};
+use crate::lockfile::{LockFile, LOCK_FILE};
+use crate::metadata::installed_distributions;
+use crate::resolver::Resolution;
+use crate::wheel::install_resolution;
+use crate::settings::Config;
+use crate::utils::*;
+
+fn backup_dir() -> PathBuf {
+    PathBuf::from(venv_dir()).join(".ppm-transaction")
+}
+
+/// A set of venv changes that is either committed together with project.toml
+/// and ppm.lock, or rolled back to the state the venv was in before
+pub struct Transaction {
+    // normalized name -> version before the transaction
+    snapshot: HashMap<String, String>,
+    // original path -> backup copy
+    backups: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Transaction {
+
+    /// snapshot the venv and back up the files of the packages about to change,
+    /// `changes` maps package keys to their target version, `None` for removals
+    pub fn begin(changes: &[(String, Option<String>)]) -> Result<Transaction, ()> {
+        let dists = installed_distributions()?;
+        let snapshot = dists.iter().map(|(k, d)| (k.clone(), d.version.clone())).collect();
+
+        let _ = fs::remove_dir_all(backup_dir());
+        if let Err(e) = fs::create_dir_all(backup_dir()) {
+            eprint(format!("Could not create backup directory: {}", e));
+            return Err(());
+        }
+
+        let mut backups = vec![];
+        for (key, target) in changes.iter() {
+            let dist = match dists.get(key) {
+                Some(dist) if target.as_ref() != Some(&dist.version) => dist,
+                _ => continue,
+            };
+            for file in dist.record_files() {
+                if !file.is_file() {
+                    continue;
+                }
+                let backup = backup_dir().join(backups.len().to_string());
+                if let Err(e) = fs::copy(&file, &backup) {
+                    eprint(format!("Could not back up {}: {}", file.display(), e));
+                    let _ = fs::remove_dir_all(backup_dir());
+                    return Err(());
+                }
+                backups.push((file, backup));
+            }
+        }
+
+        Ok(Transaction { snapshot, backups })
+    }
+
+    /// put the venv back the way it was when the transaction began
+    pub fn rollback(self) {
+        wprint("Rolling back changes to the virtual environment".to_owned());
+
+        // remove whatever got installed or upgraded in the meantime
+        if let Ok(dists) = installed_distributions() {
+            for (key, dist) in dists.iter() {
+                if self.snapshot.get(key) == Some(&dist.version) {
+                    continue;
+                }
+                remove_files(&dist.record_files());
+                let _ = fs::remove_dir_all(&dist.dist_info);
+            }
+        }
+
+        let mut failed = 0;
+        for (original, backup) in self.backups.iter() {
+            if let Some(parent) = original.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            if fs::copy(backup, original).is_err() {
+                failed += 1;
+            }
+        }
+        let _ = fs::remove_dir_all(backup_dir());
+
+        if failed > 0 {
+            eprint(format!("Could not restore {} files, run 'ppm install' to repair the venv", failed));
+        } else {
+            iprint("Virtual environment restored".to_owned());
+        }
+    }
+
+    /// write project.toml and ppm.lock, both renamed into place only after both are staged
+    pub fn commit(self, conf: &Config, lock: Option<&LockFile>) -> Result<(), ()> {
+        // the manifest goes last so it never points at a lock that wasn't written
+        let mut staged = vec![];
+        if let Some(lock) = lock {
+            staged.push((LOCK_FILE, toml::to_string(lock).unwrap()));
+        }
+        staged.push(("project.toml", toml::to_string(conf).unwrap()));
+
+        for (path, contents) in staged.iter() {
+            if let Err(e) = fs::write(format!("{}.tmp", path), contents) {
+                eprint(format!("Could not write {}: {}", path, e));
+                for (path, _) in staged.iter() {
+                    let _ = fs::remove_file(format!("{}.tmp", path));
+                }
+                self.rollback();
+                return Err(());
+            }
+        }
+        // keep the previous lock so it can be put back if project.toml can't be replaced
+        let lock_backup = backup_dir().join(LOCK_FILE);
+        let had_lock = lock.is_some() && fs::copy(LOCK_FILE, &lock_backup).is_ok();
+        for (i, (path, _)) in staged.iter().enumerate() {
+            if let Err(e) = fs::rename(format!("{}.tmp", path), path) {
+                eprint(format!("Could not write {}: {}", path, e));
+                for (path, _) in staged[i..].iter() {
+                    let _ = fs::remove_file(format!("{}.tmp", path));
+                }
+                if lock.is_some() && i > 0 {
+                    let restored = if had_lock { fs::copy(&lock_backup, LOCK_FILE).map(|_| ()) } else { fs::remove_file(LOCK_FILE) };
+                    if restored.is_err() {
+                        eprint(format!("Could not restore {}", LOCK_FILE));
+                    }
+                }
+                self.rollback();
+                return Err(());
+            }
+        }
+
+        let _ = fs::remove_dir_all(backup_dir());
+        Ok(())
+    }
+}
+
+/// install a resolution inside a new transaction, rolling back if pip fails
+pub fn apply_resolution(resolution: &Resolution, require_hashes: bool) -> Result<Transaction, ()> {
+    let tx = Transaction::begin(&resolution.targets())?;
+    if !install_resolution(resolution, require_hashes) {
+        eprint("Failed to install packages".to_owned());
+        tx.rollback();
+        return Err(());
+    }
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::sync::{Mutex, MutexGuard};
+
+    // the transaction works on the project in the current directory, one test at a time
+    static CWD: Mutex<()> = Mutex::new(());
+
+    struct Project {
+        previous: PathBuf,
+        dir: PathBuf,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl Project {
+        fn new(name: &str) -> Project {
+            let lock = CWD.lock().unwrap_or_else(|e| e.into_inner());
+            let dir = std::env::temp_dir().join(format!("ppm-test-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("venv/lib/python3.11/site-packages")).unwrap();
+            let previous = std::env::current_dir().unwrap();
+            std::env::set_current_dir(&dir).unwrap();
+            Project { previous, dir, _lock: lock }
+        }
+
+        fn site_packages(&self) -> PathBuf {
+            self.dir.join("venv/lib/python3.11/site-packages")
+        }
+
+        /// lay a distribution out the way an installer would, RECORD included
+        fn install(&self, name: &str, version: &str, files: &[(&str, &str)]) {
+            let site = self.site_packages();
+            let dist_info = format!("{}-{}.dist-info", name, version);
+            fs::create_dir_all(site.join(&dist_info)).unwrap();
+            fs::write(site.join(&dist_info).join("METADATA"), format!("Metadata-Version: 2.1\nName: {}\nVersion: {}\n", name, version)).unwrap();
+            let mut record = vec![format!("{}/METADATA,,", dist_info), format!("{}/RECORD,,", dist_info)];
+            for (path, contents) in files {
+                fs::create_dir_all(site.join(path).parent().unwrap()).unwrap();
+                fs::write(site.join(path), contents).unwrap();
+                record.push(format!("{},,", path));
+            }
+            fs::write(site.join(&dist_info).join("RECORD"), record.join("\n") + "\n").unwrap();
+        }
+
+        fn read(&self, path: &str) -> Option<String> {
+            fs::read_to_string(self.site_packages().join(path)).ok()
+        }
+    }
+
+    impl Drop for Project {
+        fn drop(&mut self) {
+            let _ = std::env::set_current_dir(&self.previous);
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn config() -> Config {
+        toml::from_str("[project]\nname = \"p\"\nversion = \"0.1\"\ndescription = \"\"\n\n[packages]\npkg = \"2.0\"\n\n[scripts]\n").unwrap()
+    }
+
+    fn empty_lock() -> LockFile {
+        LockFile::from_resolution(&[], &Resolution { packages: Default::default() })
+    }
+
+    /// what a failed upgrade of pkg 1.0 to 2.0 leaves behind, plus a new dependency
+    fn half_upgrade(project: &Project) -> Transaction {
+        project.install("pkg", "1.0", &[("pkg/__init__.py", "v1\n"), ("pkg/old.py", "old\n")]);
+        let tx = Transaction::begin(&[("pkg".to_string(), Some("2.0".to_string())), ("dep".to_string(), Some("1.0".to_string()))]).unwrap();
+        assert!(backup_dir().is_dir());
+
+        let site = project.site_packages();
+        fs::remove_dir_all(site.join("pkg")).unwrap();
+        fs::remove_dir_all(site.join("pkg-1.0.dist-info")).unwrap();
+        project.install("pkg", "2.0", &[("pkg/__init__.py", "v2\n")]);
+        project.install("dep", "1.0", &[("dep.py", "dep\n")]);
+        tx
+    }
+
+    fn assert_restored(project: &Project) {
+        let dists = installed_distributions().unwrap();
+        assert_eq!(dists.len(), 1);
+        assert_eq!(dists["pkg"].version, "1.0");
+        assert_eq!(project.read("pkg/__init__.py").as_deref(), Some("v1\n"));
+        assert_eq!(project.read("pkg/old.py").as_deref(), Some("old\n"));
+        assert_eq!(project.read("dep.py"), None);
+        assert!(!backup_dir().exists());
+    }
+
+    #[test]
+    fn rollback_restores_the_venv() {
+        let project = Project::new("rollback");
+        half_upgrade(&project).rollback();
+        assert_restored(&project);
+    }
+
+    #[test]
+    fn commit_writes_the_lock_and_manifest() {
+        let project = Project::new("commit");
+        let tx = half_upgrade(&project);
+        tx.commit(&config(), Some(&empty_lock())).unwrap();
+
+        assert_eq!(installed_distributions().unwrap()["pkg"].version, "2.0");
+        assert!(fs::read_to_string("project.toml").unwrap().contains("pkg = \"2.0\""));
+        assert!(LockFile::load_from_file(LOCK_FILE).is_ok());
+        assert!(!Path::new("ppm.lock.tmp").exists() && !Path::new("project.toml.tmp").exists());
+        assert!(!backup_dir().exists());
+    }
+
+    #[test]
+    fn commit_failing_halfway_puts_the_lock_back() {
+        let project = Project::new("commit-fails");
+        fs::write(LOCK_FILE, "# the previous lock\n").unwrap();
+        // project.toml can't be renamed over a directory, so the lock is replaced first and then restored
+        fs::create_dir_all("project.toml/in-the-way").unwrap();
+
+        let tx = half_upgrade(&project);
+        assert!(tx.commit(&config(), Some(&empty_lock())).is_err());
+
+        assert_eq!(fs::read_to_string(LOCK_FILE).unwrap(), "# the previous lock\n");
+        assert!(!Path::new("ppm.lock.tmp").exists() && !Path::new("project.toml.tmp").exists());
+        assert_restored(&project);
+
+        // without a previous lock the new one is removed again
+        fs::remove_file(LOCK_FILE).unwrap();
+        let tx = half_upgrade(&project);
+        assert!(tx.commit(&config(), Some(&empty_lock())).is_err());
+        assert!(!Path::new(LOCK_FILE).exists());
+        assert_restored(&project);
+    }
+}
@@ -1,5 +1,5 @@
 use colored::*;
-use std::{path::Path, process::{Command}, io::{self, Write}};
+use std::{path::{Path, PathBuf}, process::{Command}, io::{self, Write}, fs, collections::HashSet, sync::OnceLock};
 
 
 pub fn eprint(msg: String) {
@@ -14,6 +14,133 @@ pub fn iprint(msg: String) {
     println!("{} {}", "•".bright_green().bold(), msg.bright_green().bold());
 }
 
+/// write a file by renaming a temp file over it, so readers never see a partial write
+pub fn write_atomic(path: &str, contents: &str) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, contents)?;
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// quote an argument for the shell `ppm run` uses: cmd on windows, bash elsewhere
+pub fn shell_quote(arg: &str) -> String {
+    shell_quote_for(if cfg!(target_os = "windows") { "cmd" } else { "bash" }, arg)
+}
+
+/// quote an argument so `shell` passes it on as it is: cmd, powershell, pwsh or a posix shell
+pub fn shell_quote_for(shell: &str, arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    match shell {
+        "cmd" => cmd_quote(arg),
+        "powershell" | "pwsh" => format!("'{}'", arg.replace('\'', "''")),
+        _ => format!("'{}'", arg.replace('\'', "'\\''")),
+    }
+}
+
+/// quote for the program's own argument parsing, then `^`-escape everything cmd would
+/// act on, the quotes included so cmd never sees a quoted section and `%VAR%` isn't expanded
+fn cmd_quote(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            },
+            c => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            },
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+
+    let mut escaped = String::new();
+    for c in quoted.chars() {
+        if "()%!^\"<>&|".contains(c) {
+            escaped.push('^');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// quote and join arguments for the shell
+pub fn shell_join(args: &[String]) -> String {
+    args.iter().map(|a| shell_quote(a)).collect::<Vec<String>>().join(" ")
+}
+
+/// split a command line into arguments the way a posix shell would, without expanding anything
+pub fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err(format!("unterminated quote in '{}'", line)),
+                    }
+                }
+            },
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => current.push(c),
+                            Some(c) => {
+                                current.push('\\');
+                                current.push(c);
+                            },
+                            None => return Err(format!("unterminated quote in '{}'", line)),
+                        },
+                        Some(c) => current.push(c),
+                        None => return Err(format!("unterminated quote in '{}'", line)),
+                    }
+                }
+            },
+            '\\' if !cfg!(target_os = "windows") => {
+                in_arg = true;
+                if let Some(c) = chars.next() {
+                    current.push(c);
+                }
+            },
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            },
+            c => {
+                in_arg = true;
+                current.push(c);
+            },
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
 pub fn project_exists(name: &String, is_init: bool) -> bool {
     if is_init {
         if Path::new("project.toml").exists() {
@@ -28,28 +155,108 @@ pub fn project_exists(name: &String, is_init: bool) -> bool {
     return false;
 }
 pub fn check_venv_dir_exists() -> bool  {
-    if Path::new(&"./venv/Scripts/").exists() {
+    if Path::new(&venv_executable("python")).exists() {
         return true;
     }
     return false;
 }
 
-pub fn get_pkg_version(pkg: &String) -> Result<String, ()> {
-    let url = format!("https://pypi.org/pypi/{}/json", pkg);
-    let resp = reqwest::blocking::get(&url);
-    match resp {
-        Ok(resp) => {
-            let json: serde_json::Value = resp.json().unwrap();
-            let version = json["info"]["version"].as_str().unwrap();
-            Ok(version.to_string())
-        },
-        Err(e) => {
-            eprint("Failed to retrieve package version".to_string());
-            eprint(e.to_string());
-            Err(())
+static VENV_DIR: OnceLock<String> = OnceLock::new();
+
+/// the project venv directory, `venv` in the `[project]` table of project.toml or `./venv`,
+/// read once from the project in the current directory
+pub fn venv_dir() -> String {
+    VENV_DIR.get_or_init(|| {
+        // read on its own, the venv is needed by commands that run before (or without) a valid config
+        let configured = fs::read_to_string("project.toml")
+            .ok()
+            .and_then(|contents| contents.parse::<toml::Value>().ok())
+            .and_then(|value| value.get("project")?.get("venv")?.as_str().map(|dir| dir.to_string()));
+        match configured {
+            Some(dir) if !dir.trim().is_empty() => dir,
+            _ => "./venv".to_string(),
+        }
+    }).clone()
+}
+
+/// use `dir` as the venv of a project that doesn't have a project.toml yet,
+/// has no effect once the venv directory was looked up
+pub fn set_venv_dir(dir: &str) {
+    let _ = VENV_DIR.set(dir.to_string());
+}
+
+/// path to an executable inside the project venv
+pub fn venv_executable(name: &str) -> String {
+    if cfg!(target_os = "windows") {
+        format!("{}/Scripts/{}.exe", venv_dir(), name)
+    } else {
+        format!("{}/bin/{}", venv_dir(), name)
+    }
+}
+
+/// set up a command the way `activate` sets up a shell: the absolute venv bin dir
+/// first on PATH, VIRTUAL_ENV set and PYTHONHOME unset
+pub fn activate_venv(cmd: &mut Command) {
+    let dir = venv_dir();
+    let dir = dir.trim_start_matches("./");
+    let venv = std::env::current_dir().map(|cwd| cwd.join(dir)).unwrap_or_else(|_| PathBuf::from(dir));
+    let bin = venv.join(if cfg!(target_os = "windows") { "Scripts" } else { "bin" });
+
+    // a PATH already set on the command, by the project environment say, is kept
+    let path = cmd.get_envs().find(|(key, _)| *key == "PATH").and_then(|(_, value)| value.map(|v| v.to_os_string()));
+    let mut paths = vec![bin];
+    if let Some(path) = path.or_else(|| std::env::var_os("PATH")) {
+        paths.extend(std::env::split_paths(&path));
+    }
+    if let Ok(path) = std::env::join_paths(paths) {
+        cmd.env("PATH", path);
+    }
+    cmd.env("VIRTUAL_ENV", &venv);
+    cmd.env_remove("PYTHONHOME");
+}
+
+/// look a program up in a PATH value, trying the PATHEXT extensions on windows
+pub fn find_executable(name: &str, path: &std::ffi::OsStr) -> Option<PathBuf> {
+    if Path::new(name).components().count() > 1 {
+        return Some(PathBuf::from(name));
+    }
+    let extensions: Vec<String> = if cfg!(target_os = "windows") {
+        let pathext = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+        std::iter::once(String::new()).chain(pathext.split(';').map(|e| e.to_lowercase())).collect()
+    } else {
+        vec![String::new()]
+    };
+    std::env::split_paths(path)
+        .flat_map(|dir| extensions.iter().map(move |ext| dir.join(format!("{}{}", name, ext))))
+        .find(|candidate| candidate.is_file())
+}
+
+/// the venv interpreter, or the system one when there is no venv yet
+pub fn venv_python() -> String {
+    if check_venv_dir_exists() {
+        venv_executable("python")
+    } else {
+        "python".to_string()
+    }
+}
+
+/// locate the site-packages directory of the project venv
+pub fn venv_site_packages() -> Option<PathBuf> {
+    // windows layout
+    let venv = PathBuf::from(venv_dir());
+    let win_path = venv.join("Lib").join("site-packages");
+    if win_path.exists() {
+        return Some(win_path);
+    }
+    // posix layout, venv/lib/pythonX.Y/site-packages
+    let lib_dir = fs::read_dir(venv.join("lib")).ok()?;
+    for entry in lib_dir.flatten() {
+        let site_packages = entry.path().join("site-packages");
+        if entry.file_name().to_string_lossy().starts_with("python") && site_packages.exists() {
+            return Some(site_packages);
         }
     }
-    
+    None
 }
 
 pub fn setup_venv(venv_path: String) -> Result<(), ()> {
@@ -86,51 +293,108 @@ pub fn ask_if_create_venv() -> bool {
     }
 }
 
-/// parse name and version of package if it was name==version
-pub fn parse_version(pkg: String) -> (String, String) {
-    if pkg.contains("==") {
-        let mut pkg_split = pkg.split("==");
-        let pkg_name = pkg_split.next().unwrap();
-        let pkg_version = pkg_split.next().unwrap();
-        return (pkg_name.to_string(), pkg_version.to_string());
+/// ask for a value on the terminal, `default` when nothing is typed
+pub fn ask(question: &str, default: &str) -> String {
+    let mut answer = String::new();
+    if default.is_empty() {
+        print!("{}", format!("[?] {}: ", question).green().bold());
     } else {
-        return (pkg.to_string(), "".to_string());
+        print!("{}", format!("[?] {} ({}): ", question, default).green().bold());
+    }
+    io::stdout().flush().unwrap();
+    if io::stdin().read_line(&mut answer).is_err() || answer.trim().is_empty() {
+        return default.to_string();
     }
+    answer.trim().to_string()
 }
 
-/// install the specifed package
-pub fn install_package(pkg: String) -> bool {
-    if !check_venv_dir_exists() {
-        eprint("Virtual Environment Not Found".to_owned());
-        return false;
-    }
-    iprint(format!("Installing '{}'", pkg));
-    let venv = Command::new("./venv/Scripts/pip.exe")
-        .arg("install")
-        .arg(pkg)
-        .spawn();
-    if venv.is_err() {
-        eprint(venv.unwrap_err().to_string());
-        return false;
+/// ask a yes/no question, yes when nothing is typed
+pub fn confirm(question: &str) -> bool {
+    matches!(ask(question, "y").to_lowercase().as_str(), "y" | "yes")
+}
+
+/// delete files and any directories they leave empty (ignoring bytecode caches)
+pub fn remove_files(files: &[PathBuf]) {
+    let mut dirs = HashSet::new();
+    for file in files.iter() {
+        let _ = fs::remove_file(file);
+        if let Some(parent) = file.parent() {
+            dirs.insert(parent.to_path_buf());
+        }
     }
-    let venv = venv.unwrap();           
-
-    match venv.wait_with_output() {
-        Ok(output) => {
-            if !output.status.success() {
-                println!("{}", String::from_utf8_lossy(&output.stderr));
-                return false;
-            } else {
-                println!("{}", String::from_utf8_lossy(&output.stdout));
-                return true;
-            }
-        },
-        Err(e) => {
-            eprint(e.to_string());
-            return false;
+    // deepest directories first
+    let mut dirs: Vec<PathBuf> = dirs.into_iter().collect();
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+    for dir in dirs.iter() {
+        if only_bytecode_left(dir) {
+            let _ = fs::remove_dir_all(dir);
         }
     }
+}
 
+fn only_bytecode_left(dir: &Path) -> bool {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.flatten().all(|e| e.file_name() == "__pycache__")
 }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn leaves_safe_arguments_alone() {
+        for shell in ["bash", "cmd", "powershell"] {
+            assert_eq!(shell_quote_for(shell, "file-1.txt"), "file-1.txt");
+            assert_eq!(shell_quote_for(shell, "--opt=a,b"), "--opt=a,b");
+        }
+    }
+
+    #[test]
+    fn quotes_for_posix_shells() {
+        assert_eq!(shell_quote_for("bash", "a b"), "'a b'");
+        assert_eq!(shell_quote_for("sh", "it's"), "'it'\\''s'");
+        assert_eq!(shell_quote_for("bash", "$HOME; rm -rf /"), "'$HOME; rm -rf /'");
+        assert_eq!(shell_quote_for("bash", ""), "''");
+    }
+
+    #[test]
+    fn quotes_for_powershell() {
+        assert_eq!(shell_quote_for("powershell", "it's $x"), "'it''s $x'");
+        assert_eq!(shell_quote_for("pwsh", ""), "''");
+    }
+
+    #[test]
+    fn quotes_and_escapes_for_cmd() {
+        assert_eq!(shell_quote_for("cmd", "a b"), "^\"a b^\"");
+        assert_eq!(shell_quote_for("cmd", "100% & more"), "^\"100^% ^& more^\"");
+        assert_eq!(shell_quote_for("cmd", "say \"hi\""), "^\"say \\^\"hi\\^\"^\"");
+        assert_eq!(shell_quote_for("cmd", "C:\\my dir\\"), "^\"C:\\my dir\\\\^\"");
+        assert_eq!(shell_quote_for("cmd", "!x!"), "^\"^!x^!^\"");
+    }
+
+    #[test]
+    fn splits_quoted_arguments() {
+        assert_eq!(split_args("echo  'a b' \"c \\\"d\\\"\" e").unwrap(), strings(&["echo", "a b", "c \"d\"", "e"]));
+        assert_eq!(split_args("x '' \"\"").unwrap(), strings(&["x", "", ""]));
+        assert_eq!(split_args("pre'fix 'post").unwrap(), strings(&["prefix post"]));
+        assert_eq!(split_args("  ").unwrap(), Vec::<String>::new());
+        assert!(split_args("echo 'open").is_err());
+        assert!(split_args("echo \"open").is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn round_trips_through_posix_quoting() {
+        assert_eq!(split_args("a\\ b c\\'d").unwrap(), strings(&["a b", "c'd"]));
+        let args = strings(&["plain", "with space", "it's", "\"quoted\"", "$VAR", "back\\slash", ""]);
+        assert_eq!(split_args(&shell_join(&args)).unwrap(), args);
+    }
+}
@@ -0,0 +1,410 @@
+use std::{cmp::Ordering, fmt};
+
+/// A PEP 440 version, e.g. `1!2.0.1rc1.post2.dev3+local.1`
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub epoch: u64,
+    pub release: Vec<u64>,
+    pub pre: Option<(u8, u64)>,
+    pub post: Option<u64>,
+    pub dev: Option<u64>,
+    pub local: Vec<String>,
+    raw: String,
+}
+
+fn take_number(s: &str) -> (Option<u64>, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    if end == 0 {
+        return (None, s);
+    }
+    (s[..end].parse().ok(), &s[end..])
+}
+
+fn strip_sep(s: &str) -> &str {
+    s.strip_prefix(['.', '-', '_']).unwrap_or(s)
+}
+
+impl Version {
+    pub fn parse(input: &str) -> Option<Version> {
+        let raw = input.trim().to_string();
+        let lower = raw.to_lowercase();
+        let mut s = lower.strip_prefix('v').unwrap_or(&lower);
+
+        let (public, local) = match s.split_once('+') {
+            Some((public, local)) => (public, local),
+            None => (s, ""),
+        };
+        s = public;
+
+        let mut epoch = 0;
+        if let Some((e, rest)) = s.split_once('!') {
+            epoch = e.parse().ok()?;
+            s = rest;
+        }
+
+        let mut release = vec![];
+        loop {
+            let (num, rest) = take_number(s);
+            release.push(num?);
+            s = rest;
+            match s.strip_prefix('.') {
+                Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => s = rest,
+                _ => break,
+            }
+        }
+
+        let mut pre = None;
+        let trimmed = strip_sep(s);
+        for (prefix, kind) in [("alpha", 0), ("beta", 1), ("preview", 2), ("pre", 2), ("rc", 2), ("a", 0), ("b", 1), ("c", 2)] {
+            if let Some(rest) = trimmed.strip_prefix(prefix) {
+                let (num, rest) = take_number(strip_sep(rest));
+                pre = Some((kind, num.unwrap_or(0)));
+                s = rest;
+                break;
+            }
+        }
+
+        let mut post = None;
+        if let Some(rest) = s.strip_prefix('-').filter(|r| r.starts_with(|c: char| c.is_ascii_digit())) {
+            let (num, rest) = take_number(rest);
+            post = num;
+            s = rest;
+        } else {
+            let trimmed = strip_sep(s);
+            for prefix in ["post", "rev", "r"] {
+                if let Some(rest) = trimmed.strip_prefix(prefix) {
+                    let (num, rest) = take_number(strip_sep(rest));
+                    post = Some(num.unwrap_or(0));
+                    s = rest;
+                    break;
+                }
+            }
+        }
+
+        let mut dev = None;
+        if let Some(rest) = strip_sep(s).strip_prefix("dev") {
+            let (num, rest) = take_number(strip_sep(rest));
+            dev = Some(num.unwrap_or(0));
+            s = rest;
+        }
+
+        if !s.is_empty() {
+            return None;
+        }
+
+        let local = if local.is_empty() {
+            vec![]
+        } else {
+            local.split(['.', '-', '_']).map(|l| l.to_string()).collect()
+        };
+
+        Some(Version { epoch, release, pre, post, dev, local, raw })
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        self.pre.is_some() || self.dev.is_some()
+    }
+
+    /// the version without its local label
+    pub fn public(&self) -> Version {
+        let mut public = self.clone();
+        public.local = vec![];
+        public
+    }
+
+    fn release_at(&self, i: usize) -> u64 {
+        self.release.get(i).copied().unwrap_or(0)
+    }
+
+    fn cmp_release(&self, other: &Version) -> Ordering {
+        let len = self.release.len().max(other.release.len());
+        for i in 0..len {
+            match self.release_at(i).cmp(&other.release_at(i)) {
+                Ordering::Equal => continue,
+                ord => return ord,
+            }
+        }
+        Ordering::Equal
+    }
+
+    // ordering keys as in the `packaging` library, i64::MIN / MAX stand in for -inf / +inf
+    fn pre_key(&self) -> (i64, i64) {
+        match self.pre {
+            Some((kind, num)) => (kind as i64, num as i64),
+            None if self.post.is_none() && self.dev.is_some() => (i64::MIN, 0),
+            None => (i64::MAX, 0),
+        }
+    }
+
+    fn post_key(&self) -> i64 {
+        self.post.map(|p| p as i64).unwrap_or(i64::MIN)
+    }
+
+    fn dev_key(&self) -> i64 {
+        self.dev.map(|d| d as i64).unwrap_or(i64::MAX)
+    }
+
+    fn cmp_local(&self, other: &Version) -> Ordering {
+        for (a, b) in self.local.iter().zip(other.local.iter()) {
+            let ord = match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Ok(_), Err(_)) => Ordering::Greater,
+                (Err(_), Ok(_)) => Ordering::Less,
+                (Err(_), Err(_)) => a.cmp(b),
+            };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        self.local.len().cmp(&other.local.len())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> Ordering {
+        self.epoch.cmp(&other.epoch)
+            .then_with(|| self.cmp_release(other))
+            .then_with(|| self.pre_key().cmp(&other.pre_key()))
+            .then_with(|| self.post_key().cmp(&other.post_key()))
+            .then_with(|| self.dev_key().cmp(&other.dev_key()))
+            .then_with(|| self.cmp_local(other))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Version) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+
+/// A single version clause like `>=1.2` or `==2.*`
+#[derive(Debug, Clone)]
+pub struct Specifier {
+    pub op: String,
+    pub version: String,
+}
+
+impl Specifier {
+    pub fn parse(input: &str) -> Option<Specifier> {
+        let input = input.trim();
+        for op in ["===", "~=", "==", "!=", "<=", ">=", "<", ">"] {
+            if let Some(version) = input.strip_prefix(op) {
+                return Some(Specifier { op: op.to_string(), version: version.trim().to_string() });
+            }
+        }
+        None
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        match Version::parse(self.version.trim_end_matches(".*")) {
+            Some(v) => v.is_prerelease(),
+            None => false,
+        }
+    }
+
+    fn prefix_match(candidate: &Version, prefix: &str) -> bool {
+        let prefix = match Version::parse(prefix) {
+            Some(p) => p,
+            None => return false,
+        };
+        candidate.epoch == prefix.epoch
+            && prefix.release.iter().enumerate().all(|(i, r)| candidate.release_at(i) == *r)
+    }
+
+    pub fn contains(&self, candidate: &Version) -> bool {
+        if self.op == "===" {
+            return candidate.to_string() == self.version;
+        }
+        if let Some(prefix) = self.version.strip_suffix(".*") {
+            let matched = Self::prefix_match(candidate, prefix);
+            return if self.op == "!=" { !matched } else { matched };
+        }
+
+        let spec = match Version::parse(&self.version) {
+            Some(v) => v,
+            None => return false,
+        };
+        // local labels only matter if the specifier has one
+        let cand = if spec.local.is_empty() { candidate.public() } else { candidate.clone() };
+
+        match self.op.as_str() {
+            "==" => cand == spec,
+            "!=" => cand != spec,
+            "<=" => cand <= spec,
+            ">=" => cand >= spec,
+            // `<V` excludes pre-releases of V, `>V` excludes post-releases of V
+            "<" => cand < spec && (spec.is_prerelease() || !cand.is_prerelease() || cand.cmp_release(&spec) != Ordering::Equal),
+            ">" => cand > spec && (spec.post.is_some() || cand.post.is_none() || cand.cmp_release(&spec) != Ordering::Equal),
+            "~=" => {
+                if spec.release.len() < 2 {
+                    return false;
+                }
+                let prefix = spec.release[..spec.release.len() - 1]
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<String>>()
+                    .join(".");
+                let prefix = if spec.epoch > 0 { format!("{}!{}", spec.epoch, prefix) } else { prefix };
+                cand >= spec && Self::prefix_match(&cand, &prefix)
+            },
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Specifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.op, self.version)
+    }
+}
+
+
+/// A comma separated list of specifiers, all of which have to match
+#[derive(Debug, Clone, Default)]
+pub struct SpecifierSet {
+    pub specifiers: Vec<Specifier>,
+}
+
+impl SpecifierSet {
+    pub fn parse(input: &str) -> Option<SpecifierSet> {
+        let mut specifiers = vec![];
+        for part in input.split(',') {
+            if part.trim().is_empty() {
+                continue;
+            }
+            specifiers.push(Specifier::parse(part)?);
+        }
+        Some(SpecifierSet { specifiers })
+    }
+
+    pub fn contains(&self, candidate: &Version) -> bool {
+        self.specifiers.iter().all(|s| s.contains(candidate))
+    }
+
+    /// pre-releases are only considered when a specifier mentions one
+    pub fn allows_prereleases(&self) -> bool {
+        self.specifiers.iter().any(|s| s.is_prerelease())
+    }
+}
+
+impl fmt::Display for SpecifierSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = self.specifiers.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        Version::parse(s).unwrap()
+    }
+
+    fn spec(s: &str) -> SpecifierSet {
+        SpecifierSet::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parses_all_segments() {
+        let version = v("1!2.0.1rc1.post2.dev3+local.1");
+        assert_eq!(version.epoch, 1);
+        assert_eq!(version.release, vec![2, 0, 1]);
+        assert_eq!(version.pre, Some((2, 1)));
+        assert_eq!(version.post, Some(2));
+        assert_eq!(version.dev, Some(3));
+        assert_eq!(version.local, vec!["local", "1"]);
+        assert_eq!(version.to_string(), "1!2.0.1rc1.post2.dev3+local.1");
+    }
+
+    #[test]
+    fn normalizes_spellings() {
+        assert_eq!(v("1.0-alpha.1"), v("1.0a1"));
+        assert_eq!(v("1.0-1"), v("1.0.post1"));
+        assert_eq!(v("v1.0"), v("1.0"));
+        assert_eq!(v("1.0"), v("1.0.0"));
+        assert!(Version::parse("1.0foo").is_none());
+        assert!(Version::parse("").is_none());
+    }
+
+    #[test]
+    fn orders_like_pep_440() {
+        let ordered = [
+            "1.0.dev0", "1.0a1.dev1", "1.0a1", "1.0b2", "1.0rc1", "1.0", "1.0+local", "1.0.post1.dev0",
+            "1.0.post1", "1.1", "1.10", "1!0.1",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn orders_local_labels() {
+        assert!(v("1.0+abc") < v("1.0+1"));
+        assert!(v("1.0+1") < v("1.0+1.1"));
+        assert!(v("1.0+2") < v("1.0+10"));
+    }
+
+    #[test]
+    fn matches_comparisons() {
+        assert!(spec(">=1.0,<2").contains(&v("1.5")));
+        assert!(!spec(">=1.0,<2").contains(&v("2.0")));
+        assert!(spec("!=1.5").contains(&v("1.4")));
+        assert!(!spec("!=1.5").contains(&v("1.5.0")));
+        assert!(spec("<=1.0").contains(&v("1.0")));
+        assert!(spec("").contains(&v("0.1")));
+    }
+
+    #[test]
+    fn exclusive_bounds_skip_pre_and_post_releases() {
+        assert!(!spec("<2.0").contains(&v("2.0rc1")));
+        assert!(spec("<2.0rc2").contains(&v("2.0rc1")));
+        assert!(!spec(">1.0").contains(&v("1.0.post1")));
+        assert!(spec(">1.0.post1").contains(&v("1.0.post2")));
+    }
+
+    #[test]
+    fn matches_wildcards_and_compatible_releases() {
+        assert!(spec("==2.*").contains(&v("2.7.1")));
+        assert!(!spec("==2.*").contains(&v("3.0")));
+        assert!(spec("!=2.*").contains(&v("3.0")));
+        assert!(spec("~=1.4.2").contains(&v("1.4.9")));
+        assert!(!spec("~=1.4.2").contains(&v("1.5")));
+        assert!(spec("~=1.4").contains(&v("1.9")));
+        assert!(!spec("~=1.4").contains(&v("2.0")));
+        assert!(!spec("~=1").contains(&v("1.0")));
+    }
+
+    #[test]
+    fn local_labels_only_count_when_specified() {
+        assert!(spec("==1.0").contains(&v("1.0+cpu")));
+        assert!(!spec("==1.0+gpu").contains(&v("1.0+cpu")));
+        assert!(spec("===1.0+cpu").contains(&v("1.0+cpu")));
+        assert!(!spec("===1.0").contains(&v("1.0.0")));
+    }
+
+    #[test]
+    fn allows_prereleases_when_mentioned() {
+        assert!(!spec(">=1.0").allows_prereleases());
+        assert!(spec(">=1.0b1").allows_prereleases());
+        assert!(SpecifierSet::parse("1.0").is_none());
+        assert_eq!(spec(">=1.0, <2").to_string(), ">=1.0,<2");
+    }
+}
@@ -0,0 +1,241 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::{collections::{BTreeMap, HashSet}, env, path::{Component, Path, PathBuf}, process::{Child, Command}, sync::mpsc::{self, Receiver, RecvTimeoutError}, thread, time::{Duration, Instant, SystemTime}};
+use crate::process::{catch_signals, received_signal, spawn_group, terminate_group};
+use crate::settings::WatchConfig;
+use crate::utils::*;
+
+/// how often the child and signals are checked, and the files when there are no file events
+const POLL_INTERVAL: u64 = 250;
+const DEFAULT_DEBOUNCE: u64 = 300;
+
+/// directories never worth watching, whether .gitignore mentions them or not, besides the venv
+const SKIPPED_DIRS: [&str; 2] = [".git", "__pycache__"];
+
+/// a path without its `./` parts, to compare paths relative to the project
+fn relative(path: &Path) -> PathBuf {
+    path.components().filter(|c| !matches!(c, Component::CurDir)).collect()
+}
+
+/// globs without a `/` match the file name anywhere, like in .gitignore
+fn glob_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns.iter() {
+        let pattern = pattern.trim_start_matches("./");
+        let full = if pattern.contains('/') { pattern.to_string() } else { format!("**/{}", pattern) };
+        let glob = GlobBuilder::new(&full)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| format!("Invalid watch pattern '{}': {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// modification time and size of every watched file
+type Snapshot = BTreeMap<String, (Option<SystemTime>, u64)>;
+
+/// Finds the project files `--watch` restarts on
+struct Watcher {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    /// file events of the watched directories, polling every time when unavailable
+    notifier: Option<(RecommendedWatcher, Receiver<()>)>,
+    /// directories with a file event watch, each watched on its own so ignored ones are left out
+    watched: HashSet<PathBuf>,
+    poll_interval: Duration,
+    last_poll: Instant,
+}
+
+impl Watcher {
+    fn new(conf: &WatchConfig) -> Result<Watcher, String> {
+        let include = if conf.include.is_empty() { None } else { Some(glob_set(&conf.include)?) };
+        let (tx, rx) = mpsc::channel();
+        let notifier = match notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if event.map(|e| !e.kind.is_access()).unwrap_or(true) {
+                let _ = tx.send(());
+            }
+        }) {
+            Ok(notifier) => Some((notifier, rx)),
+            Err(e) => {
+                wprint(format!("File events unavailable ({}), polling for changes", e));
+                None
+            }
+        };
+        Ok(Watcher {
+            include,
+            exclude: glob_set(&conf.exclude)?,
+            notifier,
+            watched: HashSet::new(),
+            poll_interval: Duration::from_millis(conf.poll_interval.unwrap_or(POLL_INTERVAL)),
+            last_poll: Instant::now(),
+        })
+    }
+
+    /// wait up to `timeout` for something to change, true when the files should be compared
+    fn wait(&mut self, timeout: Duration) -> bool {
+        let Some((_, events)) = &self.notifier else {
+            thread::sleep(timeout);
+            if self.last_poll.elapsed() < self.poll_interval {
+                return false;
+            }
+            self.last_poll = Instant::now();
+            return true;
+        };
+        match events.recv_timeout(timeout) {
+            Ok(()) => {
+                // one look at the files covers every event so far
+                while events.try_recv().is_ok() {}
+                true
+            },
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => {
+                wprint("File events stopped, polling for changes".to_string());
+                self.notifier = None;
+                true
+            },
+        }
+    }
+
+    /// add file event watches for directories that weren't watched yet
+    fn watch_dirs(&mut self, dirs: Vec<PathBuf>) {
+        let Some((notifier, _)) = self.notifier.as_mut() else { return };
+        let dirs: HashSet<PathBuf> = dirs.into_iter().collect();
+        for dir in dirs.iter() {
+            if !self.watched.contains(dir) {
+                if let Err(e) = notifier.watch(dir, RecursiveMode::NonRecursive) {
+                    wprint(format!("Could not watch {}: {}", dir.display(), e));
+                }
+            }
+        }
+        // watches of removed directories go away with them
+        self.watched = dirs;
+    }
+
+    fn watches(&self, path: &str) -> bool {
+        self.include.as_ref().map(|i| i.is_match(path)).unwrap_or(true) && !self.exclude.is_match(path)
+    }
+
+    fn snapshot(&mut self) -> Snapshot {
+        let mut files = Snapshot::new();
+        let mut dirs = vec![];
+        let venv = relative(Path::new(&venv_dir()));
+        let walker = WalkBuilder::new(".")
+            .hidden(false)
+            .require_git(false)
+            .filter_entry(move |e| !(e.depth() > 0 && e.file_type().map(|t| t.is_dir()).unwrap_or(false)
+                && (SKIPPED_DIRS.iter().any(|d| e.file_name() == *d) || relative(e.path()) == venv)))
+            .build();
+        for entry in walker.filter_map(Result::ok) {
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                dirs.push(entry.into_path());
+                continue;
+            }
+            if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+                continue;
+            }
+            let path = entry.path().strip_prefix(".").unwrap_or(entry.path());
+            let path = path.to_string_lossy().replace('\\', "/");
+            if !self.watches(&path) {
+                continue;
+            }
+            if let Ok(meta) = entry.metadata() {
+                files.insert(path, (meta.modified().ok(), meta.len()));
+            }
+        }
+        self.watch_dirs(dirs);
+        files
+    }
+}
+
+/// the first file that was added, removed or modified between two snapshots
+fn first_change(old: &Snapshot, new: &Snapshot) -> Option<String> {
+    new.iter()
+        .find(|(path, stat)| old.get(*path) != Some(*stat))
+        .or_else(|| old.iter().find(|(path, _)| !new.contains_key(*path)))
+        .map(|(path, _)| path.clone())
+}
+
+fn spawn(argv: &[String]) -> Option<Child> {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprint(format!("Could not find the ppm executable: {}", e));
+            return None;
+        }
+    };
+    match spawn_group(Command::new(exe).args(argv)) {
+        Ok(child) => Some(child),
+        Err(e) => {
+            eprint(format!("Could not start 'ppm {}': {}", argv.join(" "), e));
+            None
+        }
+    }
+}
+
+/// run `ppm <argv>` and run it again every time a watched file changes, until interrupted
+pub fn watch(argv: &[String], conf: &WatchConfig) -> i32 {
+    if !Path::new("project.toml").exists() {
+        eprint("Could not find project.toml".to_owned());
+        return 1;
+    }
+    let mut watcher = match Watcher::new(conf) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprint(e);
+            return 1;
+        }
+    };
+    let debounce = Duration::from_millis(conf.debounce.unwrap_or(DEFAULT_DEBOUNCE));
+    catch_signals();
+
+    let mut files = watcher.snapshot();
+    iprint(format!("Watching {} files, press Ctrl+C to stop", files.len()));
+    let mut child = spawn(argv);
+    loop {
+        if let Some(signal) = received_signal() {
+            if let Some(child) = child.as_mut() {
+                terminate_group(child);
+            }
+            return 128 + signal;
+        }
+        let changed = watcher.wait(Duration::from_millis(POLL_INTERVAL));
+
+        if let Some(running) = child.as_mut() {
+            if let Ok(Some(status)) = running.try_wait() {
+                let code = status.code().map(|c| format!("code {}", c)).unwrap_or_else(|| "a signal".to_string());
+                iprint(format!("Exited with {}, waiting for changes", code));
+                // clean up whatever it left running in its group
+                terminate_group(running);
+                child = None;
+            }
+        }
+
+        if !changed {
+            continue;
+        }
+        let mut current = watcher.snapshot();
+        let changed = match first_change(&files, &current) {
+            Some(path) => path,
+            None => continue,
+        };
+        // editors save in several steps, wait until the files settle
+        loop {
+            thread::sleep(debounce);
+            watcher.wait(Duration::ZERO);
+            let next = watcher.snapshot();
+            if next == current || received_signal().is_some() {
+                break;
+            }
+            current = next;
+        }
+        files = current;
+
+        iprint(format!("{} changed, restarting", changed));
+        if let Some(child) = child.as_mut() {
+            terminate_group(child);
+        }
+        child = spawn(argv);
+    }
+}
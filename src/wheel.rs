@@ -0,0 +1,817 @@
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::{fs, io::{Cursor, Read}, path::{Component, Path, PathBuf}, collections::HashMap};
+use crate::cache::Cache;
+use crate::environment::apply_project_env;
+use crate::index::{Artifact, PackageIndex};
+use crate::metadata::{installed_distributions, Distribution};
+use crate::resolver::Resolution;
+use crate::utils::*;
+
+/// The parts of a wheel filename, `{name}-{version}(-{build})?-{python}-{abi}-{platform}.whl`
+#[derive(Debug, Clone)]
+pub struct WheelName {
+    pub filename: String,
+    pub name: String,
+    pub version: String,
+    pub python: Vec<String>,
+    pub abi: Vec<String>,
+    pub platform: Vec<String>,
+}
+
+impl WheelName {
+    pub fn parse(filename: &str) -> Option<WheelName> {
+        let stem = filename.strip_suffix(".whl")?;
+        let parts: Vec<&str> = stem.split('-').collect();
+        if parts.len() != 5 && parts.len() != 6 {
+            return None;
+        }
+        let n = parts.len();
+        // tags can be compressed sets like `py2.py3`
+        let split = |s: &str| s.split('.').map(|t| t.to_string()).collect::<Vec<String>>();
+        Some(WheelName {
+            filename: filename.to_string(),
+            name: parts[0].to_string(),
+            version: parts[1].to_string(),
+            python: split(parts[n - 3]),
+            abi: split(parts[n - 2]),
+            platform: split(parts[n - 1]),
+        })
+    }
+}
+
+fn open_archive(data: &[u8]) -> Result<zip::ZipArchive<Cursor<&[u8]>>, String> {
+    zip::ZipArchive::new(Cursor::new(data)).map_err(|e| e.to_string())
+}
+
+/// the top level `*.dist-info` directory of a wheel
+fn dist_info_dir(archive: &zip::ZipArchive<Cursor<&[u8]>>) -> Result<String, String> {
+    archive
+        .file_names()
+        .find_map(|f| {
+            let (dir, file) = f.split_once('/')?;
+            if dir.ends_with(".dist-info") && file == "WHEEL" { Some(dir.to_string()) } else { None }
+        })
+        .ok_or_else(|| "no .dist-info directory in wheel".to_string())
+}
+
+fn read_entry(archive: &mut zip::ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<Vec<u8>, String> {
+    let mut entry = archive.by_name(name).map_err(|e| format!("{}: {}", name, e))?;
+    let mut buf = vec![];
+    entry.read_to_end(&mut buf).map_err(|e| e.to_string())?;
+    Ok(buf)
+}
+
+/// read the METADATA file out of a wheel on disk
+pub fn read_wheel_metadata(path: &Path) -> Result<String, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let mut archive = open_archive(&data)?;
+    let dist_info = dist_info_dir(&archive)?;
+    let metadata = read_entry(&mut archive, &format!("{}/METADATA", dist_info))?;
+    Ok(String::from_utf8_lossy(&metadata).to_string())
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// hash in the format used by RECORD files
+fn record_hash(data: &[u8]) -> String {
+    format!("sha256={}", base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(data)))
+}
+
+const INTERPRETER_SCRIPT: &str = "\
+import sys, sysconfig, os, json
+paths = sysconfig.get_paths()
+try:
+    libc = os.confstr('CS_GNU_LIBC_VERSION') or ''
+except (AttributeError, ValueError, OSError):
+    libc = ''
+print(json.dumps({
+    'executable': sys.executable,
+    'version': list(sys.version_info[:2]),
+    'implementation': sys.implementation.name,
+    'soabi': sysconfig.get_config_var('SOABI') or '',
+    'platform': sysconfig.get_platform(),
+    'libc': libc,
+    'purelib': paths['purelib'],
+    'platlib': paths['platlib'],
+    'scripts': paths['scripts'],
+    'data': paths['data'],
+}))
+";
+
+/// Install locations and supported wheel tags of the venv interpreter
+pub struct Interpreter {
+    pub executable: String,
+    pub version: (u64, u64),
+    pub purelib: PathBuf,
+    pub platlib: PathBuf,
+    pub scripts: PathBuf,
+    pub data: PathBuf,
+    tags: HashMap<(String, String, String), usize>,
+}
+
+impl Interpreter {
+    pub fn detect(python: &str) -> Result<Interpreter, String> {
+        let output = std::process::Command::new(python)
+            .arg("-c")
+            .arg(INTERPRETER_SCRIPT)
+            .output()
+            .map_err(|e| format!("Could not run '{}': {}", python, e))?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).to_string());
+        }
+        let info: serde_json::Value = serde_json::from_slice(&output.stdout).map_err(|e| e.to_string())?;
+        let get = |key: &str| info[key].as_str().unwrap_or("").to_string();
+        let version = (info["version"][0].as_u64().unwrap_or(3), info["version"][1].as_u64().unwrap_or(0));
+
+        let implementation = match get("implementation").as_str() {
+            "cpython" => "cp".to_string(),
+            "pypy" => "pp".to_string(),
+            other => other.to_string(),
+        };
+        let tags = supported_tags(&implementation, version, &get("soabi"), &platform_tags(&get("platform"), &get("libc")));
+
+        Ok(Interpreter {
+            executable: get("executable"),
+            version,
+            purelib: PathBuf::from(get("purelib")),
+            platlib: PathBuf::from(get("platlib")),
+            scripts: PathBuf::from(get("scripts")),
+            data: PathBuf::from(get("data")),
+            tags: tags.into_iter().enumerate().map(|(i, t)| (t, i)).collect(),
+        })
+    }
+
+    /// lower is better, `None` if the wheel can't be installed here
+    pub fn tag_priority(&self, wheel: &WheelName) -> Option<usize> {
+        let mut best = None;
+        for py in wheel.python.iter() {
+            for abi in wheel.abi.iter() {
+                for plat in wheel.platform.iter() {
+                    if let Some(&p) = self.tags.get(&(py.clone(), abi.clone(), plat.clone())) {
+                        best = Some(best.map_or(p, |b: usize| b.min(p)));
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// the most specific compatible wheel among the artifacts
+    pub fn best_wheel<'a>(&self, artifacts: &'a [Artifact]) -> Option<&'a Artifact> {
+        artifacts
+            .iter()
+            .filter(|a| a.is_wheel())
+            .filter_map(|a| Some((self.tag_priority(&WheelName::parse(&a.filename)?)?, a)))
+            .min_by_key(|(p, _)| *p)
+            .map(|(_, a)| a)
+    }
+}
+
+fn platform_tags(platform: &str, libc: &str) -> Vec<String> {
+    let normalized = platform.replace(['-', '.'], "_");
+    let mut tags = vec![];
+
+    if let Some(arch) = normalized.strip_prefix("linux_") {
+        let glibc_minor = libc
+            .strip_prefix("glibc ")
+            .and_then(|v| v.split('.').nth(1))
+            .and_then(|m| m.parse::<u32>().ok());
+        if let Some(minor) = glibc_minor {
+            let lowest = if arch == "x86_64" || arch == "i686" { 5 } else { 17 };
+            for m in (lowest..=minor).rev() {
+                tags.push(format!("manylinux_2_{}_{}", m, arch));
+                // the legacy aliases of PEP 513, 571 and 599
+                match m {
+                    17 => tags.push(format!("manylinux2014_{}", arch)),
+                    12 => tags.push(format!("manylinux2010_{}", arch)),
+                    5 => tags.push(format!("manylinux1_{}", arch)),
+                    _ => {}
+                }
+            }
+        }
+        tags.push(normalized.clone());
+    } else if platform.starts_with("macosx") {
+        // macosx-11.0-arm64
+        let parts: Vec<&str> = platform.split('-').collect();
+        let version: Vec<u32> = parts.get(1).unwrap_or(&"10.9").split('.').filter_map(|v| v.parse().ok()).collect();
+        let arch = parts.get(2).unwrap_or(&"x86_64").to_string();
+        let (major, minor) = (version.first().copied().unwrap_or(10), version.get(1).copied().unwrap_or(0));
+
+        let mut versions = vec![];
+        if major >= 11 {
+            for maj in (11..=major).rev() {
+                versions.push((maj, 0));
+            }
+            for min in (0..=15).rev() {
+                versions.push((10, min));
+            }
+        } else {
+            for min in (0..=minor).rev() {
+                versions.push((10, min));
+            }
+        }
+        let arches: Vec<&str> = match arch.as_str() {
+            "arm64" => vec!["arm64", "universal2"],
+            "x86_64" => vec!["x86_64", "intel", "fat64", "fat3", "universal2", "universal"],
+            other => vec![other, "universal2"],
+        };
+        for (maj, min) in versions {
+            for a in arches.iter() {
+                tags.push(format!("macosx_{}_{}_{}", maj, min, a));
+            }
+        }
+    } else {
+        // win_amd64, win32, ...
+        tags.push(normalized);
+    }
+    tags
+}
+
+fn supported_tags(implementation: &str, version: (u64, u64), soabi: &str, platforms: &[String]) -> Vec<(String, String, String)> {
+    let (major, minor) = version;
+    let interp = format!("{}{}{}", implementation, major, minor);
+    let mut tags = vec![];
+    let mut push = |py: String, abi: &str, plat: &str| tags.push((py, abi.to_string(), plat.to_string()));
+
+    // cpython-311-x86_64-linux-gnu -> cp311, windows interpreters report no SOABI
+    let abi = match soabi.split('-').nth(1) {
+        Some(v) if soabi.starts_with("cpython") => format!("cp{}", v),
+        _ => interp.clone(),
+    };
+    for plat in platforms.iter() {
+        push(interp.clone(), &abi, plat);
+    }
+    if implementation == "cp" {
+        for plat in platforms.iter() {
+            for m in (2..=minor).rev() {
+                push(format!("cp{}{}", major, m), "abi3", plat);
+            }
+        }
+    }
+    for plat in platforms.iter() {
+        push(interp.clone(), "none", plat);
+    }
+    for plat in platforms.iter().map(|p| p.as_str()).chain(["any"]) {
+        if plat == "any" {
+            push(interp.clone(), "none", plat);
+        }
+        push(format!("py{}{}", major, minor), "none", plat);
+        push(format!("py{}", major), "none", plat);
+        for m in (0..minor).rev() {
+            push(format!("py{}{}", major, m), "none", plat);
+        }
+    }
+    tags
+}
+
+/// download or read an artifact and check its hash
+pub fn fetch_artifact(artifact: &Artifact) -> Result<Vec<u8>, String> {
+    let data = if artifact.is_local() {
+        fs::read(&artifact.url).map_err(|e| format!("{}: {}", artifact.url, e))?
+    } else {
+        let resp = reqwest::blocking::get(&artifact.url).map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("{} returned {}", artifact.url, resp.status()));
+        }
+        resp.bytes().map_err(|e| e.to_string())?.to_vec()
+    };
+
+    if let Some(expected) = &artifact.sha256 {
+        let actual = sha256_hex(&data);
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(format!("hash mismatch for {}, expected sha256 {} but got {}", artifact.filename, expected, actual));
+        }
+    }
+    Ok(data)
+}
+
+/// `path` relative to `base`, both absolute, using `..` where needed
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = path.iter().zip(base.iter()).take_while(|(a, b)| a == b).count();
+    let mut rel = PathBuf::new();
+    for _ in common..base.len() {
+        rel.push("..");
+    }
+    for c in path[common..].iter() {
+        rel.push(c.as_os_str());
+    }
+    rel
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    let _ = fs::set_permissions(path, fs::Permissions::from_mode(0o755));
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) {}
+
+/// console and gui scripts declared in entry_points.txt
+fn parse_entry_points(contents: &str) -> Vec<(String, String, String)> {
+    let mut scripts = vec![];
+    let mut section = String::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_string();
+            continue;
+        }
+        if section != "console_scripts" && section != "gui_scripts" {
+            continue;
+        }
+        if let Some((name, target)) = line.split_once('=') {
+            // drop extras like `module:func [extra]`
+            let target = target.split('[').next().unwrap_or("").trim();
+            if let Some((module, attr)) = target.split_once(':') {
+                scripts.push((name.trim().to_string(), module.trim().to_string(), attr.trim().to_string()));
+            }
+        }
+    }
+    scripts
+}
+
+struct WheelInstall<'a> {
+    interp: &'a Interpreter,
+    root: PathBuf,
+    record: Vec<(PathBuf, String, usize)>,
+}
+
+impl WheelInstall<'_> {
+    fn write(&mut self, dest: &Path, contents: &[u8], executable: bool) -> Result<(), String> {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        fs::write(dest, contents).map_err(|e| format!("{}: {}", dest.display(), e))?;
+        if executable {
+            make_executable(dest);
+        }
+        self.record.push((dest.to_path_buf(), record_hash(contents), contents.len()));
+        Ok(())
+    }
+
+    fn write_entry_point(&mut self, name: &str, module: &str, attr: &str) -> Result<(), String> {
+        let import_name = attr.split('.').next().unwrap_or(attr);
+        let script = format!(
+            "# -*- coding: utf-8 -*-\nimport re\nimport sys\nfrom {} import {}\nif __name__ == '__main__':\n    sys.argv[0] = re.sub(r'(-script\\.pyw|\\.exe)?$', '', sys.argv[0])\n    sys.exit({}())\n",
+            module, import_name, attr
+        );
+        if cfg!(target_os = "windows") {
+            // without a launcher exe, a .cmd wrapper next to the script does the job
+            let script_path = self.interp.scripts.join(format!("{}-script.py", name));
+            self.write(&script_path, script.as_bytes(), false)?;
+            let wrapper = format!("@\"{}\" \"%~dp0{}-script.py\" %*\r\n", self.interp.executable, name);
+            self.write(&self.interp.scripts.join(format!("{}.cmd", name)), wrapper.as_bytes(), false)
+        } else {
+            let contents = format!("#!{}\n{}", self.interp.executable, script);
+            self.write(&self.interp.scripts.join(name), contents.as_bytes(), true)
+        }
+    }
+
+    fn write_record(&self, dist_info: &Path) -> Result<(), String> {
+        let record_path = dist_info.join("RECORD");
+        let mut lines: Vec<String> = self.record
+            .iter()
+            .filter(|(path, _, _)| *path != record_path)
+            .map(|(path, hash, size)| format!("{},{},{}", relative_to(path, &self.root).to_string_lossy().replace('\\', "/"), hash, size))
+            .collect();
+        lines.push(format!("{},,", relative_to(&record_path, &self.root).to_string_lossy().replace('\\', "/")));
+        fs::write(&record_path, lines.join("\n") + "\n").map_err(|e| e.to_string())
+    }
+}
+
+/// unpack a wheel into the venv, replacing `existing` if it is installed
+pub fn install_wheel(interp: &Interpreter, data: &[u8], filename: &str, existing: Option<&Distribution>) -> Result<(), String> {
+    let mut archive = open_archive(data)?;
+    let dist_info = dist_info_dir(&archive)?;
+    let data_dir = format!("{}.data", dist_info.trim_end_matches(".dist-info"));
+
+    let wheel_file = String::from_utf8_lossy(&read_entry(&mut archive, &format!("{}/WHEEL", dist_info))?).to_string();
+    let purelib = wheel_file.lines().any(|l| l.replace(' ', "").eq_ignore_ascii_case("Root-Is-Purelib:true"));
+    let root = if purelib { interp.purelib.clone() } else { interp.platlib.clone() };
+
+    if let Some(existing) = existing {
+        remove_files(&existing.record_files());
+        let _ = fs::remove_dir_all(&existing.dist_info);
+    }
+
+    let mut install = WheelInstall { interp, root: root.clone(), record: vec![] };
+    let dist_name = dist_info.split('-').next().unwrap_or("").to_string();
+    let headers = interp.data
+        .join("include")
+        .join("site")
+        .join(format!("python{}.{}", interp.version.0, interp.version.1))
+        .join(&dist_name);
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| e.to_string())?;
+        if entry.is_dir() {
+            continue;
+        }
+        // refuse absolute paths and any `..`, it could climb out of a `.data` scheme directory
+        let path = match entry.enclosed_name() {
+            Some(path) if path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) => path.to_path_buf(),
+            _ => return Err(format!("unsafe path '{}' in {}", entry.name(), filename)),
+        };
+        let mut contents = vec![];
+        entry.read_to_end(&mut contents).map_err(|e| e.to_string())?;
+
+        let mut executable = false;
+        let dest = match path.strip_prefix(&data_dir) {
+            Ok(rest) => {
+                let mut parts = rest.components();
+                let scheme = parts.next().map(|c| c.as_os_str().to_string_lossy().to_string()).unwrap_or_default();
+                let rest = parts.as_path();
+                match scheme.as_str() {
+                    "purelib" => interp.purelib.join(rest),
+                    "platlib" => interp.platlib.join(rest),
+                    "headers" => headers.join(rest),
+                    "data" => interp.data.join(rest),
+                    "scripts" => {
+                        executable = true;
+                        // `#!python` shebangs point at the venv interpreter
+                        if contents.starts_with(b"#!python") {
+                            let end = contents.iter().position(|&b| b == b'\n').unwrap_or(contents.len());
+                            let mut rewritten = format!("#!{}", interp.executable).into_bytes();
+                            rewritten.extend_from_slice(&contents[end..]);
+                            contents = rewritten;
+                        }
+                        interp.scripts.join(rest)
+                    },
+                    other => return Err(format!("unknown data directory '{}' in {}", other, filename)),
+                }
+            },
+            Err(_) => root.join(&path),
+        };
+        install.write(&dest, &contents, executable)?;
+    }
+
+    let dist_info_path = root.join(&dist_info);
+    if let Ok(entry_points) = fs::read_to_string(dist_info_path.join("entry_points.txt")) {
+        for (name, module, attr) in parse_entry_points(&entry_points) {
+            install.write_entry_point(&name, &module, &attr)?;
+        }
+    }
+    install.write(&dist_info_path.join("INSTALLER"), b"ppm\n", false)?;
+    install.write_record(&dist_info_path)
+}
+
+/// fetch an artifact through the cache, `allowed` are the hashes recorded in the lock
+fn fetch_cached(cache: Option<&Cache>, artifact: &Artifact, allowed: &[String]) -> Result<Vec<u8>, String> {
+    let cached = match (cache, &artifact.sha256) {
+        (Some(cache), Some(sha256)) => cache.get(sha256),
+        _ => None,
+    };
+    let data = match cached {
+        Some(data) => data,
+        None => {
+            let data = fetch_artifact(artifact)?;
+            if let Some(cache) = cache {
+                if !artifact.is_local() {
+                    let _ = cache.put(&data);
+                }
+            }
+            data
+        }
+    };
+
+    if !allowed.is_empty() {
+        let actual = sha256_hex(&data);
+        if !allowed.contains(&actual) {
+            return Err(format!("hash mismatch for {}: sha256 {} is not one of the expected hashes", artifact.filename, actual));
+        }
+    }
+    Ok(data)
+}
+
+/// the sdist of a release, downloaded by pip when the index doesn't list one
+fn fetch_sdist(cache: Option<&Cache>, name: &str, version: &str, artifacts: &[Artifact], allowed: &[String], build_dir: &Path) -> Result<PathBuf, String> {
+    if let Some(sdist) = artifacts.iter().find(|a| a.filename.ends_with(".tar.gz") || a.filename.ends_with(".zip")) {
+        let data = fetch_cached(cache, sdist, allowed)?;
+        let path = build_dir.join(&sdist.filename);
+        fs::write(&path, data).map_err(|e| e.to_string())?;
+        return Ok(path);
+    }
+
+    let download_dir = build_dir.join("sdist");
+    let mut source_args = vec![];
+    if allowed.is_empty() {
+        source_args.push(format!("{}=={}", name, version));
+    } else {
+        // only with one of the locked hashes
+        let hashes = allowed.iter().map(|h| format!(" --hash=sha256:{}", h)).collect::<String>();
+        let req_file = build_dir.join("requirements.txt");
+        fs::write(&req_file, format!("{}=={}{}\n", name, version, hashes)).map_err(|e| e.to_string())?;
+        source_args.push("--require-hashes".to_string());
+        source_args.push("-r".to_string());
+        source_args.push(req_file.to_string_lossy().to_string());
+    }
+    let mut pip = std::process::Command::new(venv_executable("pip"));
+    apply_project_env(&mut pip);
+    let status = pip
+        .args(["download", "--no-deps", "--no-binary", ":all:", "-d"])
+        .arg(&download_dir)
+        .args(&source_args)
+        .status()
+        .map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("pip could not download the sdist of {} {}", name, version));
+    }
+    fs::read_dir(&download_dir)
+        .map_err(|e| e.to_string())?
+        .flatten()
+        .map(|e| e.path())
+        .find(|p| p.is_file())
+        .ok_or_else(|| format!("pip did not download the sdist of {} {}", name, version))
+}
+
+/// build a wheel from the sdist with `pip wheel`, returning the sdist's sha256 with the wheel
+fn build_wheel(cache: Option<&Cache>, name: &str, version: &str, artifacts: &[Artifact], allowed: &[String]) -> Result<(String, String, Vec<u8>), String> {
+    let build_dir = std::env::temp_dir().join(format!("ppm-build-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&build_dir);
+    fs::create_dir_all(&build_dir).map_err(|e| e.to_string())?;
+    let result = build_in(cache, name, version, artifacts, allowed, &build_dir);
+    let _ = fs::remove_dir_all(&build_dir);
+    result
+}
+
+fn build_in(cache: Option<&Cache>, name: &str, version: &str, artifacts: &[Artifact], allowed: &[String], build_dir: &Path) -> Result<(String, String, Vec<u8>), String> {
+    let sdist = fetch_sdist(cache, name, version, artifacts, allowed, build_dir)?;
+    let sdist_sha256 = sha256_hex(&fs::read(&sdist).map_err(|e| e.to_string())?);
+
+    iprint(format!("Building wheel for {} {}", name, version));
+    let wheel_dir = build_dir.join("wheels");
+    let mut pip = std::process::Command::new(venv_executable("pip"));
+    apply_project_env(&mut pip);
+    let status = pip
+        .arg("wheel")
+        .arg("--no-deps")
+        .arg("-w")
+        .arg(&wheel_dir)
+        .arg(&sdist)
+        .status()
+        .map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("pip could not build {} {}", name, version));
+    }
+
+    let built = fs::read_dir(&wheel_dir)
+        .map_err(|e| e.to_string())?
+        .flatten()
+        .find(|e| e.file_name().to_string_lossy().ends_with(".whl"))
+        .ok_or_else(|| format!("pip did not produce a wheel for {} {}", name, version))?;
+    let filename = built.file_name().to_string_lossy().to_string();
+    let data = fs::read(built.path()).map_err(|e| e.to_string())?;
+    Ok((sdist_sha256, filename, data))
+}
+
+/// install every package of a resolution, building wheels with pip for sdist-only releases,
+/// with `require_hashes` nothing is downloaded without a hash to check it against
+pub fn install_resolution(resolution: &Resolution, require_hashes: bool) -> bool {
+    if !check_venv_dir_exists() {
+        eprint("Virtual Environment Not Found".to_owned());
+        return false;
+    }
+    let interp = match Interpreter::detect(&venv_executable("python")) {
+        Ok(interp) => interp,
+        Err(e) => {
+            eprint(format!("Could not inspect the venv interpreter: {}", e));
+            return false;
+        }
+    };
+    let installed = match installed_distributions() {
+        Ok(installed) => installed,
+        Err(_) => return false,
+    };
+
+    let cache = Cache::open();
+    if cache.is_none() {
+        wprint("Could not open the ppm cache, downloads won't be cached".to_owned());
+    }
+    let mut index = PackageIndex::new();
+    for (key, pkg) in resolution.packages.iter() {
+        let existing = installed.get(key);
+        let version = pkg.version.to_string();
+        if existing.map(|d| d.version == version).unwrap_or(false) {
+            continue;
+        }
+        if require_hashes && pkg.hashes.is_empty() {
+            eprint(format!("No hashes known for {} {}, refusing to install it with --require-hashes", pkg.name, version));
+            return false;
+        }
+
+        let mut artifacts = if pkg.artifacts.is_empty() {
+            match index.metadata(&pkg.name, &version) {
+                Ok(meta) => meta.files,
+                Err(e) => {
+                    eprint(e);
+                    return false;
+                }
+            }
+        } else {
+            pkg.artifacts.clone()
+        };
+        // wheels built before from one of the sdists this package may come from
+        let built: Vec<Artifact> = match cache.as_ref() {
+            Some(cache) => pkg.hashes.iter().flat_map(|h| cache.built_wheels(h)).collect(),
+            None => vec![],
+        };
+        artifacts.extend(built.iter().cloned());
+
+        let (filename, data) = match interp.best_wheel(&artifacts) {
+            Some(wheel) => {
+                // wheels we built ourselves are trusted through the hash of their sdist
+                let allowed: &[String] = if built.iter().any(|b| b.url == wheel.url) { &[] } else { &pkg.hashes };
+                match fetch_cached(cache.as_ref(), wheel, allowed) {
+                    Ok(data) => (wheel.filename.clone(), data),
+                    Err(e) => {
+                        eprint(e);
+                        return false;
+                    }
+                }
+            },
+            // no compatible wheel, pip builds one from the sdist
+            None => match build_wheel(cache.as_ref(), &pkg.name, &version, &artifacts, &pkg.hashes) {
+                Ok((sdist_sha256, filename, data)) => {
+                    if let Some(cache) = cache.as_ref() {
+                        let _ = cache.store_built_wheel(&sdist_sha256, &filename, &data);
+                    }
+                    (filename, data)
+                },
+                Err(e) => {
+                    eprint(e);
+                    return false;
+                }
+            },
+        };
+
+        if let Err(e) = install_wheel(&interp, &data, &filename, existing) {
+            eprint(format!("Failed to install {}: {}", filename, e));
+            return false;
+        }
+        iprint(format!("Installed {} {}", pkg.name, pkg.version));
+    }
+    true
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn wheel(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (name, contents) in files.iter() {
+            zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn interpreter(root: &Path) -> Interpreter {
+        let platforms = platform_tags("linux-x86_64", "glibc 2.31");
+        let tags = supported_tags("cp", (3, 11), "cpython-311-x86_64-linux-gnu", &platforms);
+        Interpreter {
+            executable: root.join("bin").join("python").to_string_lossy().to_string(),
+            version: (3, 11),
+            purelib: root.join("lib").join("site-packages"),
+            platlib: root.join("lib64").join("site-packages"),
+            scripts: root.join("bin"),
+            data: root.to_path_buf(),
+            tags: tags.into_iter().enumerate().map(|(i, t)| (t, i)).collect(),
+        }
+    }
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("ppm-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        root
+    }
+
+    #[test]
+    fn parses_wheel_names() {
+        let name = WheelName::parse("six-1.16.0-py2.py3-none-any.whl").unwrap();
+        assert_eq!(name.name, "six");
+        assert_eq!(name.version, "1.16.0");
+        assert_eq!(name.python, vec!["py2", "py3"]);
+        assert_eq!(name.abi, vec!["none"]);
+        assert_eq!(name.platform, vec!["any"]);
+
+        let name = WheelName::parse("pkg-2.0-1build-cp311-cp311-manylinux_2_17_x86_64.manylinux2014_x86_64.whl").unwrap();
+        assert_eq!(name.version, "2.0");
+        assert_eq!(name.platform, vec!["manylinux_2_17_x86_64", "manylinux2014_x86_64"]);
+
+        assert!(WheelName::parse("pkg-1.0.tar.gz").is_none());
+        assert!(WheelName::parse("pkg-1.0-py3.whl").is_none());
+    }
+
+    #[test]
+    fn picks_the_most_specific_compatible_wheel() {
+        let interp = interpreter(Path::new("/venv"));
+        let artifact = |filename: &str| Artifact { filename: filename.to_string(), url: String::new(), sha256: None };
+        let artifacts = vec![
+            artifact("pkg-1.0.tar.gz"),
+            artifact("pkg-1.0-py3-none-any.whl"),
+            artifact("pkg-1.0-cp38-abi3-manylinux2014_x86_64.whl"),
+            artifact("pkg-1.0-cp311-cp311-manylinux_2_28_x86_64.whl"),
+            artifact("pkg-1.0-cp311-cp311-win_amd64.whl"),
+            artifact("pkg-1.0-cp312-cp312-manylinux_2_17_x86_64.whl"),
+        ];
+        assert_eq!(interp.best_wheel(&artifacts).unwrap().filename, "pkg-1.0-cp311-cp311-manylinux_2_28_x86_64.whl");
+        assert_eq!(interp.best_wheel(&artifacts[..3]).unwrap().filename, "pkg-1.0-cp38-abi3-manylinux2014_x86_64.whl");
+        assert_eq!(interp.best_wheel(&artifacts[..2]).unwrap().filename, "pkg-1.0-py3-none-any.whl");
+        assert!(interp.best_wheel(&artifacts[4..]).is_none());
+        // glibc 2.31 can't run wheels built for a newer one
+        assert!(interp.tag_priority(&WheelName::parse("pkg-1.0-cp311-cp311-manylinux_2_34_x86_64.whl").unwrap()).is_none());
+    }
+
+    #[test]
+    fn reads_console_and_gui_scripts() {
+        let contents = "[console_scripts]\ntool = pkg.cli:main [extra]\n\n[gui_scripts]\nview=pkg.gui:App.run\n\n[pkg.plugins]\nother = pkg.other:x\n";
+        assert_eq!(parse_entry_points(contents), vec![
+            ("tool".to_string(), "pkg.cli".to_string(), "main".to_string()),
+            ("view".to_string(), "pkg.gui".to_string(), "App.run".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn installs_files_data_and_entry_points() {
+        let root = temp_root("install");
+        let interp = interpreter(&root);
+        let data = wheel(&[
+            ("pkg/__init__.py", "VERSION = '1.0'\n"),
+            ("pkg-1.0.data/scripts/run-pkg", "#!python\nprint('hi')\n"),
+            ("pkg-1.0.data/data/share/pkg/readme.txt", "data\n"),
+            ("pkg-1.0.data/headers/pkg.h", "int x;\n"),
+            ("pkg-1.0.dist-info/METADATA", "Metadata-Version: 2.1\nName: pkg\nVersion: 1.0\n"),
+            ("pkg-1.0.dist-info/WHEEL", "Wheel-Version: 1.0\nRoot-Is-Purelib: true\nTag: py3-none-any\n"),
+            ("pkg-1.0.dist-info/entry_points.txt", "[console_scripts]\npkg-cli = pkg.cli:main\n"),
+            ("pkg-1.0.dist-info/RECORD", ""),
+        ]);
+        install_wheel(&interp, &data, "pkg-1.0-py3-none-any.whl", None).unwrap();
+
+        assert_eq!(fs::read_to_string(interp.purelib.join("pkg/__init__.py")).unwrap(), "VERSION = '1.0'\n");
+        assert_eq!(fs::read_to_string(root.join("share/pkg/readme.txt")).unwrap(), "data\n");
+        assert!(root.join("include/site/python3.11/pkg/pkg.h").is_file());
+
+        let script = fs::read_to_string(root.join("bin/run-pkg")).unwrap();
+        assert_eq!(script, format!("#!{}\nprint('hi')\n", interp.executable));
+        let cli = fs::read_to_string(root.join("bin/pkg-cli")).unwrap();
+        assert!(cli.starts_with(&format!("#!{}\n", interp.executable)));
+        assert!(cli.contains("from pkg.cli import main"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(root.join("bin/pkg-cli")).unwrap().permissions().mode() & 0o111, 0o111);
+        }
+
+        let dist_info = interp.purelib.join("pkg-1.0.dist-info");
+        assert_eq!(fs::read_to_string(dist_info.join("INSTALLER")).unwrap(), "ppm\n");
+        let record = fs::read_to_string(dist_info.join("RECORD")).unwrap();
+        let lines: Vec<&str> = record.lines().collect();
+        assert!(lines.contains(&format!("pkg/__init__.py,{},16", record_hash(b"VERSION = '1.0'\n")).as_str()));
+        assert!(lines.iter().any(|l| l.starts_with("../../bin/pkg-cli,sha256=")));
+        assert!(lines.iter().any(|l| l.starts_with("../../share/pkg/readme.txt,")));
+        assert!(lines.iter().any(|l| l.starts_with("pkg-1.0.dist-info/INSTALLER,")));
+        assert_eq!(lines.last(), Some(&"pkg-1.0.dist-info/RECORD,,"));
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn refuses_paths_outside_the_venv() {
+        let root = temp_root("unsafe");
+        let interp = interpreter(&root);
+        let data = wheel(&[
+            ("../evil.py", "x\n"),
+            ("pkg-1.0.dist-info/WHEEL", "Wheel-Version: 1.0\nRoot-Is-Purelib: true\n"),
+        ]);
+        let err = install_wheel(&interp, &data, "pkg-1.0-py3-none-any.whl", None).unwrap_err();
+        assert!(err.contains("unsafe path"), "{}", err);
+        assert!(!root.join("lib/evil.py").exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn refuses_data_paths_climbing_out_of_their_scheme() {
+        let root = temp_root("unsafe-data");
+        let interp = interpreter(&root);
+        for scheme in ["data", "purelib", "platlib", "headers", "scripts"] {
+            let entry = format!("pkg-1.0.data/{}/../../evil.txt", scheme);
+            let data = wheel(&[
+                (entry.as_str(), "x\n"),
+                ("pkg-1.0.dist-info/WHEEL", "Wheel-Version: 1.0\nRoot-Is-Purelib: true\n"),
+            ]);
+            let err = install_wheel(&interp, &data, "pkg-1.0-py3-none-any.whl", None).unwrap_err();
+            assert!(err.contains("unsafe path"), "{}: {}", scheme, err);
+        }
+        assert!(!root.join("evil.txt").exists());
+        assert!(!root.parent().unwrap().join("evil.txt").exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+}